@@ -47,7 +47,8 @@ export RUST_LOG=debug
 
 # do NOT touch unless you know what you do
 BINDLE_CONFIGURATION_SCRIPT="${_DIR}/../bindle/scripts/bindle_start.sh"
-BINDLE_CREATION_SCRIPT="${_DIR}/../bindle/scripts/bindle_create.sh"
+MLINFERENCE_MANIFEST="${_DIR}/../providers/mlinference/Cargo.toml"
+BINDLE_MODELS="${_DIR}/../bindle/models"
 BINDLE_SHUTDOWN_SCRIPT="${_DIR}/../bindle/scripts/bindle_stop.sh"
 
 ##
@@ -193,11 +194,7 @@ create_bindle() {
 
     printf "\n[bindle creation]\n"
 
-    if [ -z "$BINDLE" ] || [ ! -x $BINDLE ]; then
-      echo "You must define BINDLE_HOME or BINDLE"
-      exit 1
-    fi
-    eval '"$BINDLE_CREATION_SCRIPT"'
+    cargo run --quiet --manifest-path "$MLINFERENCE_MANIFEST" --bin mlinference-bindle -- push "$BINDLE_MODELS"/*.toml
 }
 
 # get the host id (requires wasmcloud to be running)
@@ -0,0 +1,4 @@
+//! mllabels Interface
+
+mod mllabels;
+pub use mllabels::*;
@@ -0,0 +1,238 @@
+// This file is @generated by wasmcloud/weld-codegen 0.5.0.
+// It is not intended for manual editing.
+// namespace: org.wasmcloud.interface.mllabels
+
+#[allow(unused_imports)]
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use serde::{Deserialize, Serialize};
+#[allow(unused_imports)]
+use std::{borrow::Borrow, borrow::Cow, io::Write, string::ToString};
+#[allow(unused_imports)]
+use wasmbus_rpc::{
+    cbor::*,
+    common::{
+        deserialize, message_format, serialize, Context, Message, MessageDispatch, MessageFormat,
+        SendOpts, Transport,
+    },
+    error::{RpcError, RpcResult},
+    Timestamp,
+};
+
+#[allow(dead_code)]
+pub const SMITHY_VERSION: &str = "1.0";
+
+pub type Labels = Vec<String>;
+
+// Encode Labels as CBOR and append to output stream
+#[doc(hidden)]
+#[allow(unused_mut)]
+pub fn encode_labels<W: wasmbus_rpc::cbor::Write>(
+    mut e: &mut wasmbus_rpc::cbor::Encoder<W>,
+    val: &Labels,
+) -> RpcResult<()>
+where
+    <W as wasmbus_rpc::cbor::Write>::Error: std::fmt::Display,
+{
+    e.array(val.len() as u64)?;
+    for item in val.iter() {
+        e.str(item)?;
+    }
+    Ok(())
+}
+
+// Decode Labels from cbor input stream
+#[doc(hidden)]
+pub fn decode_labels(d: &mut wasmbus_rpc::cbor::Decoder<'_>) -> Result<Labels, RpcError> {
+    let __result = {
+        if let Some(n) = d.array()? {
+            let mut arr: Vec<String> = Vec::with_capacity(n as usize);
+            for _ in 0..(n as usize) {
+                arr.push(d.str()?.to_string())
+            }
+            arr
+        } else {
+            // indefinite array
+            let mut arr: Vec<String> = Vec::new();
+            loop {
+                match d.datatype() {
+                    Err(_) => break,
+                    Ok(wasmbus_rpc::cbor::Type::Break) => break,
+                    Ok(_) => arr.push(d.str()?.to_string()),
+                }
+            }
+            arr
+        }
+    };
+    Ok(__result)
+}
+/// LabelsRequest
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LabelsRequest {
+    /// model name as configured in the provider's zoo
+    #[serde(default)]
+    pub model: String,
+}
+
+// Encode LabelsRequest as CBOR and append to output stream
+#[doc(hidden)]
+#[allow(unused_mut)]
+pub fn encode_labels_request<W: wasmbus_rpc::cbor::Write>(
+    mut e: &mut wasmbus_rpc::cbor::Encoder<W>,
+    val: &LabelsRequest,
+) -> RpcResult<()>
+where
+    <W as wasmbus_rpc::cbor::Write>::Error: std::fmt::Display,
+{
+    e.array(1)?;
+    e.str(&val.model)?;
+    Ok(())
+}
+
+// Decode LabelsRequest from cbor input stream
+#[doc(hidden)]
+pub fn decode_labels_request(
+    d: &mut wasmbus_rpc::cbor::Decoder<'_>,
+) -> Result<LabelsRequest, RpcError> {
+    let __result = {
+        let mut model: Option<String> = None;
+
+        let is_array = match d.datatype()? {
+            wasmbus_rpc::cbor::Type::Array => true,
+            wasmbus_rpc::cbor::Type::Map => false,
+            _ => {
+                return Err(RpcError::Deser(
+                    "decoding struct LabelsRequest, expected array or map".to_string(),
+                ))
+            }
+        };
+        if is_array {
+            let len = d.fixed_array()?;
+            for __i in 0..(len as usize) {
+                match __i {
+                    0 => model = Some(d.str()?.to_string()),
+                    _ => d.skip()?,
+                }
+            }
+        } else {
+            let len = d.fixed_map()?;
+            for __i in 0..(len as usize) {
+                match d.str()? {
+                    "model" => model = Some(d.str()?.to_string()),
+                    _ => d.skip()?,
+                }
+            }
+        }
+        LabelsRequest {
+            model: if let Some(__x) = model {
+                __x
+            } else {
+                return Err(RpcError::Deser(
+                    "missing field LabelsRequest.model (#0)".to_string(),
+                ));
+            },
+        }
+    };
+    Ok(__result)
+}
+/// Labels of the models served by the mlinference provider
+/// wasmbus.contractId: wasmcloud:mlinference
+/// wasmbus.providerReceive
+#[async_trait]
+pub trait MlLabels {
+    /// returns the capability contract id for this interface
+    fn contract_id() -> &'static str {
+        "wasmcloud:mlinference"
+    }
+    /// Returns the labels shipped with a model, one per output class
+    async fn get_labels(&self, ctx: &Context, arg: &LabelsRequest) -> RpcResult<Labels>;
+}
+
+/// MlLabelsReceiver receives messages defined in the MlLabels service trait
+/// Labels of the models served by the mlinference provider
+#[doc(hidden)]
+#[async_trait]
+pub trait MlLabelsReceiver: MessageDispatch + MlLabels {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> Result<Vec<u8>, RpcError> {
+        match message.method {
+            "GetLabels" => {
+                let value: LabelsRequest =
+                    wasmbus_rpc::common::decode(&message.arg, &decode_labels_request)
+                        .map_err(|e| RpcError::Deser(format!("'LabelsRequest': {}", e)))?;
+                let resp = MlLabels::get_labels(self, ctx, &value).await?;
+                let mut e = wasmbus_rpc::cbor::vec_encoder(true);
+                encode_labels(&mut e, &resp)?;
+                let buf = e.into_inner();
+                Ok(buf)
+            }
+            _ => Err(RpcError::MethodNotHandled(format!(
+                "MlLabels::{}",
+                message.method
+            ))),
+        }
+    }
+}
+
+/// MlLabelsSender sends messages to a MlLabels service
+/// Labels of the models served by the mlinference provider
+/// client for sending MlLabels messages
+#[derive(Debug)]
+pub struct MlLabelsSender<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> MlLabelsSender<T> {
+    /// Constructs a MlLabelsSender with the specified transport
+    pub fn via(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn set_timeout(&self, interval: std::time::Duration) {
+        self.transport.set_timeout(interval);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl MlLabelsSender<wasmbus_rpc::actor::prelude::WasmHost> {
+    /// Constructs a client for sending to a MlLabels provider
+    /// implementing the 'wasmcloud:mlinference' capability contract, with the "default" link
+    pub fn new() -> Self {
+        let transport =
+            wasmbus_rpc::actor::prelude::WasmHost::to_provider("wasmcloud:mlinference", "default")
+                .unwrap();
+        Self { transport }
+    }
+
+    /// Constructs a client for sending to a MlLabels provider
+    /// implementing the 'wasmcloud:mlinference' capability contract, with the specified link name
+    pub fn new_with_link(link_name: &str) -> wasmbus_rpc::error::RpcResult<Self> {
+        let transport =
+            wasmbus_rpc::actor::prelude::WasmHost::to_provider("wasmcloud:mlinference", link_name)?;
+        Ok(Self { transport })
+    }
+}
+#[async_trait]
+impl<T: Transport + std::marker::Sync + std::marker::Send> MlLabels for MlLabelsSender<T> {
+    #[allow(unused)]
+    /// Returns the labels shipped with a model, one per output class
+    async fn get_labels(&self, ctx: &Context, arg: &LabelsRequest) -> RpcResult<Labels> {
+        let mut e = wasmbus_rpc::cbor::vec_encoder(true);
+        encode_labels_request(&mut e, arg)?;
+        let buf = e.into_inner();
+        let resp = self
+            .transport
+            .send(
+                ctx,
+                Message {
+                    method: "MlLabels.GetLabels",
+                    arg: Cow::Borrowed(&buf),
+                },
+                None,
+            )
+            .await?;
+
+        let value: Labels = wasmbus_rpc::common::decode(&resp, &decode_labels)
+            .map_err(|e| RpcError::Deser(format!("'{}': Labels", e)))?;
+        Ok(value)
+    }
+}
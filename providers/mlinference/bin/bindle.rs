@@ -0,0 +1,321 @@
+//! publishes models to and fetches models from the bindle server at 'BINDLE_URL'
+//!
+//! usage:
+//!   mlinference-bindle create <model> [metadata.json] --name plus3 --version 0.1.0
+//!       [--labels labels.txt] [--target cpu --precision f32 --variant m_edgetpu.tflite:tpu:uint8]
+//!   mlinference-bindle push <invoice.toml>...
+//!   mlinference-bindle list [query]
+//!   mlinference-bindle fetch <name/version> [--out dir]
+//!   mlinference-bindle validate <invoice.toml>...
+//!
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use wasmcloud_provider_mlinference::{
+    detect_encoding, invoice_toml, validate_invoice, BindleLoader, ExecutionTarget, InvoiceSpec,
+    ModelMetadata, ParcelFile, ENCODING_ANNOTATION, LABELS_GROUP, METADATA_GROUP, MODEL_GROUP,
+    PRECISION_ANNOTATION, TARGET_ANNOTATION,
+};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "mlinference-bindle",
+    about = "Publishes models to bindle as expected by the mlinference provider"
+)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// creates an invoice from a model and its optional metadata, hashing the parcels
+    Create(Box<CreateArgs>),
+
+    /// pushes invoices and their parcels, found next to the invoice by label name
+    Push { invoices: Vec<PathBuf> },
+
+    /// lists the bindles available on the server
+    List { query: Option<String> },
+
+    /// fetches the invoice, model and metadata of a bindle
+    Fetch {
+        /// bindle id, e.g. 'plus3/0.1.0'
+        id: String,
+        #[clap(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+
+    /// validates invoices and the hashes of their parcels
+    Validate { invoices: Vec<PathBuf> },
+}
+
+#[derive(clap::Args, Debug)]
+struct CreateArgs {
+    model: PathBuf,
+    metadata: Option<PathBuf>,
+    #[clap(long)]
+    name: String,
+    #[clap(long, default_value = "0.1.0")]
+    version: String,
+    /// may be repeated
+    #[clap(long = "author")]
+    authors: Vec<String>,
+    #[clap(long)]
+    description: Option<String>,
+    /// invoice to write, defaults to '<name>.toml' next to the model
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+    /// labels of the output classes, one per line
+    #[clap(long)]
+    labels: Option<PathBuf>,
+    /// execution target of the model, annotating it as one of several variants
+    #[clap(long)]
+    target: Option<String>,
+    /// precision of the model, e.g. 'f32' or 'uint8'
+    #[clap(long)]
+    precision: Option<String>,
+    /// further variant of the model as '<file>:<target>[:<precision>]', may be repeated
+    #[clap(long = "variant")]
+    variants: Vec<String>,
+}
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[tokio::main]
+async fn main() -> CliResult<()> {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Create(create) => {
+            let CreateArgs {
+                model,
+                metadata,
+                name,
+                version,
+                authors,
+                description,
+                out,
+                labels,
+                target,
+                precision,
+                variants,
+            } = *create;
+            let mut parcels = vec![model_parcel(
+                &model,
+                target.as_deref(),
+                precision.as_deref(),
+            )?];
+            for variant in variants.iter() {
+                let mut fields = variant.split(':');
+                let path = PathBuf::from(fields.next().unwrap_or_default());
+                let target = fields
+                    .next()
+                    .ok_or_else(|| format!("variant '{}' names no target", variant))?;
+                parcels.push(model_parcel(&path, Some(target), fields.next())?);
+            }
+            if let Some(metadata) = metadata {
+                ModelMetadata::from_json(&std::fs::read(&metadata)?)?;
+                parcels.push(ParcelFile::from_path(
+                    &metadata,
+                    "application/json",
+                    &[METADATA_GROUP],
+                )?);
+            }
+            if let Some(labels) = labels {
+                parcels.push(ParcelFile::from_path(
+                    &labels,
+                    "text/plain",
+                    &[LABELS_GROUP],
+                )?);
+            }
+            let out = out.unwrap_or_else(|| parent_of(&model).join(format!("{}.toml", name)));
+            let spec = InvoiceSpec {
+                name,
+                version,
+                authors,
+                description,
+            };
+
+            std::fs::write(&out, invoice_toml(&spec, &parcels)?)?;
+            println!("wrote '{}'", out.display());
+        }
+
+        Command::Push { invoices } => {
+            let client = BindleLoader::provide("BINDLE_URL").await?;
+            let mut failed = 0;
+
+            for path in invoices.iter() {
+                if let Err(e) = push(&client, path).await {
+                    failed += 1;
+                    println!("'{}': FAILED, {}", path.display(), e);
+                }
+            }
+
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+
+        Command::List { query } => {
+            let client = BindleLoader::provide("BINDLE_URL").await?;
+            let matches = client
+                .query_invoices(bindle::QueryOptions {
+                    query,
+                    ..Default::default()
+                })
+                .await?;
+
+            for invoice in matches.invoices.iter() {
+                let description = invoice.bindle.description.clone().unwrap_or_default();
+                println!("{}\t{}", invoice.bindle.id, description);
+            }
+        }
+
+        Command::Fetch { id, out } => {
+            let client = BindleLoader::provide("BINDLE_URL").await?;
+            let invoice = client.get_invoice(id.as_str()).await?;
+            std::fs::create_dir_all(&out)?;
+
+            for parcel in invoice.parcel.iter().flatten() {
+                let data = client.get_parcel(id.as_str(), &parcel.label.sha256).await?;
+                let path = file_in(&out, &parcel.label.name)?;
+                std::fs::write(&path, data)?;
+                println!("wrote '{}'", path.display());
+            }
+
+            // names of bindles may be paths, e.g. 'example.com/mobilenet'
+            let name = invoice
+                .bindle
+                .id
+                .name()
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            let path = file_in(&out, &format!("{}.toml", name))?;
+            std::fs::write(&path, toml::to_string(&invoice)?)?;
+            println!("wrote '{}'", path.display());
+        }
+
+        Command::Validate { invoices } => {
+            let mut invalid = 0;
+
+            for path in invoices.iter() {
+                let problems = validate(path)?;
+                if problems.is_empty() {
+                    println!("'{}': ok", path.display());
+                } else {
+                    invalid += 1;
+                    for problem in problems {
+                        println!("'{}': {}", path.display(), problem);
+                    }
+                }
+            }
+
+            if invalid > 0 {
+                std::process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 'name' as file in 'out', refusing names such as '../x' or '/x' which would leave it
+fn file_in(out: &Path, name: &str) -> CliResult<PathBuf> {
+    match Path::new(name).file_name() {
+        Some(file) if file == name => Ok(out.join(file)),
+        _ => Err(format!(
+            "refusing to write '{}' outside of '{}'",
+            name,
+            out.display()
+        )
+        .into()),
+    }
+}
+
+/// pushes an invoice followed by the parcels the server is missing
+async fn push(
+    client: &bindle::client::Client<bindle::client::tokens::NoToken>,
+    path: &Path,
+) -> CliResult<()> {
+    let problems = validate(path)?;
+    if !problems.is_empty() {
+        return Err(problems.join(", ").into());
+    }
+
+    let invoice: bindle::Invoice = toml::from_str(&std::fs::read_to_string(path)?)?;
+    let id = invoice.bindle.id.clone();
+    let response = client.create_invoice(invoice).await?;
+    println!("'{}': pushed invoice '{}'", path.display(), id);
+
+    for label in response.missing.iter().flatten() {
+        let data = std::fs::read(parent_of(path).join(&label.name))?;
+        client
+            .create_parcel(id.clone(), &label.sha256, data)
+            .await?;
+        println!("'{}': pushed parcel '{}'", path.display(), label.name);
+    }
+
+    Ok(())
+}
+
+/// problems of the invoice at 'path' and of its parcels as found next to it
+fn validate(path: &Path) -> CliResult<Vec<String>> {
+    let invoice: bindle::Invoice = toml::from_str(&std::fs::read_to_string(path)?)?;
+    let mut problems = validate_invoice(&invoice);
+
+    for parcel in invoice.parcel.iter().flatten() {
+        let file = parent_of(path).join(&parcel.label.name);
+        if !file.exists() {
+            problems.push(format!("parcel '{}' not found", file.display()));
+            continue;
+        }
+
+        let local = ParcelFile::from_path(&file, &parcel.label.media_type, &[])?;
+        if local.sha256 != parcel.label.sha256 || local.size != parcel.label.size {
+            problems.push(format!(
+                "parcel '{}' has sha256 '{}' and size {}, the invoice declares '{}' and {}",
+                parcel.label.name, local.sha256, local.size, parcel.label.sha256, parcel.label.size
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// the parcel of a model, annotated by its encoding, target and precision if given
+fn model_parcel(
+    path: &Path,
+    target: Option<&str>,
+    precision: Option<&str>,
+) -> CliResult<ParcelFile> {
+    let mut parcel = ParcelFile::from_path(path, "application/octet-stream", &[MODEL_GROUP])?;
+
+    if let Some(target) = target {
+        let target: ExecutionTarget =
+            serde_json::from_value(serde_json::Value::String(target.to_string()))
+                .map_err(|_| format!("unknown target '{}'", target))?;
+        parcel = parcel.with_annotation(
+            TARGET_ANNOTATION,
+            serde_json::to_value(&target)?.as_str().unwrap_or_default(),
+        );
+
+        let encoding = detect_encoding(&std::fs::read(path)?)
+            .ok_or_else(|| format!("cannot tell the encoding of '{}'", path.display()))?;
+        parcel = parcel.with_annotation(
+            ENCODING_ANNOTATION,
+            serde_json::to_value(&encoding)?
+                .as_str()
+                .unwrap_or_default(),
+        );
+    }
+    if let Some(precision) = precision {
+        parcel = parcel.with_annotation(PRECISION_ANNOTATION, precision);
+    }
+
+    Ok(parcel)
+}
+
+fn parent_of(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
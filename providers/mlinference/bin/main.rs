@@ -1,46 +1,90 @@
 //! mlinference capability provider
 //!
-use bindle::client::{tokens::NoToken, Client};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::sync::RwLock;
-use wasmbus_rpc::provider::prelude::*;
+use wasmbus_rpc::{
+    core::{HealthCheckRequest, HealthCheckResponse},
+    provider::prelude::*,
+};
 pub(crate) use wasmcloud_interface_mlinference::{
-    InferenceInput, InferenceOutput, MlError, MlInference, MlInferenceReceiver,
+    InferenceInput, InferenceOutput, MlError, MlInference, MlInferenceReceiver, Status,
 };
-
-#[cfg(any(feature = "tflite", feature = "edgetpu"))]
-use wasmcloud_provider_mlinference::TfLiteEngine;
+pub(crate) use wasmcloud_interface_mllabels::{Labels, LabelsRequest, MlLabels, MlLabelsReceiver};
 
 use wasmcloud_provider_mlinference::{
-    get_default_inference_result, load_settings, BindleLoader, Engine, Graph, GraphEncoding,
-    GraphExecutionContext, InferenceFramework, ModelContext, ModelZoo, TractEngine,
+    drop_evicted, get_default_inference_result, load_settings, load_settings_file, model_id,
+    model_loader, requires_edge_tpu, run_warmup, split_reference, warmup_tensors, AdmissionError,
+    BindleLoader, Engine, EngineRegistry, ExecutionTarget, InferenceCache, InferenceFramework,
+    InputMonitor, Loaded, MemoryBudget, MemorySettings, ModelContext, ModelGate, ModelLoader,
+    ModelMetadata, ModelSettings, ModelZoo, Record, Recorder, ResidentKey, TfLiteOptions,
+    TokenBucket, VariantPreference, LABELS_GROUP,
 };
 
-
 /// main (via provider_main) initializes the threaded tokio executor,
 /// listens to lattice rpcs, handles actor links,
 /// and returns only when it receives a shutdown message
 ///
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // validates a configuration file offline, e.g. 'mlinference --check-config actor_config.json'
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--check-config") {
+        let path = match args.get(2) {
+            Some(p) => std::path::PathBuf::from(p),
+            None => {
+                eprintln!(
+                    "usage: {} --check-config <config.json|config.toml>",
+                    args[0]
+                );
+                std::process::exit(2);
+            }
+        };
+
+        match load_settings_file(&path) {
+            Ok(settings) => {
+                println!(
+                    "'{}': ok, {} model(s) configured",
+                    path.display(),
+                    settings.models.zoo.len()
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("'{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     tracing_log::LogTracer::init()?;
 
+    // the memory budget is provider-wide, links do not override it
+    let memory = MemorySettings::from_env(std::env::vars())?;
+    if let Some(budget) = memory.budget_bytes {
+        log::info!("loaded models are bounded to {} bytes", budget);
+    }
+
     provider_main(
-        MlInferenceProvider::default(),
+        MlInferenceProvider {
+            memory: Arc::new(MemoryBudget::new(memory.budget_bytes)),
+            ..Default::default()
+        },
         Some("mlinference".to_string()),
     )?;
 
-    if std::env::var("BINDLE_URL").is_err() {
-        log::error!("No 'BINDLE_URL' defined, verify your bindle url.");
-        return Err("No 'BINDLE_URL' defined, verify your bindle url.".into());
-    }
-
     eprintln!("mlinference provider exiting");
     Ok(())
 }
 
+/// lock of the loading of an evicted model
+type ReloadLock = Arc<tokio::sync::Mutex<()>>;
+
 /// mlinference capability provider implementation
 #[derive(Default, Clone, Provider)]
-#[services(MlInference)]
+#[services(MlInference, MlLabels)]
 struct MlInferenceProvider {
     /// map to store the assignments between the respective model
     /// and corresponding bindle path for each linked actor
@@ -58,12 +102,28 @@ struct MlInferenceProvider {
     ///     - GraphEncoding
     ///
     /// InferenceFramework corresponds to an integrated crate.
-    /// An InferenceFramework may support a multitude of Engine
-    /// An InferenceFramework may support a multitude of GraphEncoding.
     /// Engine is a wrapper of InferenceEngine
-    /// InferenceEngine defines common behavior
+    /// InferenceEngine defines common behavior and declares the
+    /// GraphEncoding and ExecutionTarget pairs it supports.
     /// GraphEncoding defines a model's encoding.
-    engines: Arc<RwLock<HashMap<InferenceFramework, Engine>>>,
+    ///
+    /// The registry holds an Engine per InferenceFramework of this build.
+    engines: Arc<EngineRegistry>,
+
+    /// rate limit of each linked actor, if configured
+    rate_limits: Arc<RwLock<HashMap<String, Arc<TokenBucket>>>>,
+
+    /// request/response recorder of each linked actor, if configured
+    recorders: Arc<RwLock<HashMap<String, Arc<Recorder>>>>,
+
+    /// loaded models of all linked actors, evicted beyond the memory budget
+    memory: Arc<MemoryBudget>,
+
+    /// settings of each linked actor, to load its evicted models again
+    links: Arc<RwLock<HashMap<String, Arc<ModelSettings>>>>,
+
+    /// serializes the loading of each evicted model, by actor and model name
+    reloads: Arc<Mutex<HashMap<ResidentKey, ReloadLock>>>,
 }
 
 /// use default implementations of provider message handlers
@@ -89,24 +149,139 @@ impl ProviderHandler for MlInferenceProvider {
             }
         };
 
-        for (_, context) in model_zoo.iter() {
-            let engine = match self.get_engine(&context).await {
-                Ok(v) => v,
-                Err(_) => {
-                    return;
-                }
-            };
-
-            engine
-                .drop_model_state(&context.graph, &context.graph_execution_context)
-                .await;
-        }
+        self.unload_models(actor_id, model_zoo).await;
 
         actor_lock.remove(actor_id);
+
+        self.links.write().await.remove(actor_id);
+
+        self.rate_limits.write().await.remove(actor_id);
+        self.recorders.write().await.remove(actor_id);
+        self.reloads
+            .lock()
+            .unwrap()
+            .retain(|(actor, _), _| actor != actor_id);
+    }
+
+    /// Reports the framework, effective execution target, residency and input drift of each linked model
+    async fn health_request(&self, _arg: &HealthCheckRequest) -> RpcResult<HealthCheckResponse> {
+        let actor_lock = self.actors.read().await;
+
+        let mut models: Vec<String> = actor_lock
+            .iter()
+            .flat_map(|(actor_id, model_zoo)| {
+                model_zoo.iter().map(move |(name, context)| {
+                    let drift = context.monitor.as_ref().map(|monitor| {
+                        let statistics = monitor.statistics();
+                        format!(
+                            ", {} drift warnings in {} requests ({} NaN, {} infinite values)",
+                            monitor.warnings(),
+                            statistics.requests,
+                            statistics.nan_count,
+                            statistics.inf_count
+                        )
+                    });
+
+                    format!(
+                        "{}/{}: {:?} on {:?}, signed by {}, {}{}",
+                        actor_id,
+                        name,
+                        context.framework,
+                        context.execution_target,
+                        context.signer.as_deref().unwrap_or("nobody"),
+                        if context.residency.is_loaded() {
+                            "loaded"
+                        } else {
+                            "not loaded"
+                        },
+                        drift.unwrap_or_default()
+                    )
+                })
+            })
+            .collect();
+        models.sort();
+
+        Ok(HealthCheckResponse {
+            healthy: true,
+            message: Some(models.join(", ")),
+        })
     }
 }
 
 impl MlInferenceProvider {
+    /// Loads the models of a link, except lazy ones, and runs their warm-up
+    async fn load_models(
+        &self,
+        actor: &str,
+        model_zoo: &mut ModelZoo,
+        settings: &ModelSettings,
+    ) -> Result<(), RpcError> {
+        for (name, context) in model_zoo.iter_mut() {
+            // options of the model take precedence over those of the link
+            let options = settings.models.zoo[name].options();
+            let warmup = options.warmup.unwrap_or_else(|| settings.warmup.clone());
+
+            // a lazy model is loaded by its first inference
+            if options.lazy_load.or(settings.lazy_load) == Some(true) {
+                log::info!(
+                    "load_models() - '{}' is loaded on its first inference",
+                    context.bindle_url
+                );
+                continue;
+            }
+
+            // the model is not evicted before its warm-up is done
+            let _in_use = context.residency.acquire();
+            let (engine, loader) = self.load_model(actor, name, context, settings).await?;
+            self.attach_model(name, context, loader.as_ref(), settings)
+                .await?;
+            let id = split_reference(&context.bindle_url).1.to_string();
+
+            // a model is ready only after its warm-up succeeded
+            if warmup.is_enabled() {
+                let samples = BindleLoader::get_warmup_samples(loader.as_ref(), &id)
+                    .await
+                    .map_err(|error| RpcError::ProviderInit(format!("{}", error)))?;
+
+                let tensors = warmup_tensors(context, samples, &warmup.fill).map_err(|error| {
+                    log::error!("warmup_tensors() failed for '{}'!", context.bindle_url);
+                    RpcError::ProviderInit(format!("{}", error))
+                })?;
+
+                let start = std::time::Instant::now();
+                run_warmup(&engine, context, &tensors, warmup.iterations)
+                    .await
+                    .map_err(|error| {
+                        log::error!("run_warmup() failed for '{}'!", context.bindle_url);
+                        RpcError::ProviderInit(format!("{}", error))
+                    })?;
+                log::info!(
+                    "warm-up of '{}' with {} inferences took {:?}",
+                    context.bindle_url,
+                    warmup.iterations,
+                    start.elapsed()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unloads the models of a link, models in use once their last inference is done
+    async fn unload_models(&self, actor: &str, model_zoo: &ModelZoo) {
+        for (name, context) in model_zoo.iter() {
+            // evicted models have no state to drop
+            if let Some(loaded) = context.residency.retire() {
+                loaded
+                    .engine
+                    .drop_model_state(&loaded.graph, &loaded.graph_execution_context)
+                    .await;
+            }
+            self.memory
+                .release(&(actor.to_string(), name.to_string()), &context.residency);
+        }
+    }
+
     async fn put_link_sub(&mut self, ld: &LinkDefinition) -> Result<bool, RpcError> {
         log::debug!("put_link_sub() - link definition is '{:?}'", ld);
 
@@ -115,13 +290,28 @@ impl MlInferenceProvider {
 
         log::debug!("put_link_sub() - just passed 'load_settings()'");
 
+        // created first, a link whose recording fails is not put in place
+        let recorder = match settings.recording.is_enabled() {
+            true => {
+                let recorder =
+                    Recorder::new(settings.recording.clone(), &ld.actor_id).map_err(|e| {
+                        log::error!("put_link_sub() - recorder could not be created: {}", e);
+                        RpcError::ProviderInit(format!("recorder: {}", e))
+                    })?;
+                log::info!("recording requests to '{}'", recorder.path().display());
+                Some(recorder)
+            }
+            false => None,
+        };
+
         let mut model_zoo: ModelZoo = ModelZoo::new();
 
         settings.models.zoo.iter().for_each(|(k, v)| {
             model_zoo.insert(
                 k.to_string(),
                 ModelContext {
-                    bindle_url: v.to_string(),
+                    bindle_url: model_id(v.reference()).to_string(),
+                    gate: Arc::new(ModelGate::new(k, settings.admission.limits_for(k))),
                     ..ModelContext::default()
                 },
             );
@@ -132,56 +322,50 @@ impl MlInferenceProvider {
             &model_zoo
         );
 
-        let bindle_client: Client<NoToken> =
-            BindleLoader::provide("BINDLE_URL").await.map_err(|error| {
-                log::error!("put_link_sub() no 'BINDLE_URL' found");
-                RpcError::ProviderInit(format!("{}", error))
-            })?;
-
         log::debug!("put_link_sub() - NOT done yet");
 
-        for (_, context) in model_zoo.iter_mut() {
-            let downloads =
-                BindleLoader::get_model_and_metadata(&bindle_client, &context.bindle_url)
-                    .await
-                    .map_err(|error| {
-                        log::error!("get_model_and_metadata() failed!");
-                        RpcError::ProviderInit(format!("{}", error))
-                    })?;
-
-            let (metadata, model_data_bytes) = downloads;
-
-            context.load_metadata(metadata).map_err(|error| {
-                log::error!("load_metadata() failed!");
-                RpcError::InvalidParameter(format!("{:?}", error))
-            })?;
-
-            // each link definition may address a different target
-            // such that it may be necessary to support multiple engines.
-            let engine = self.get_or_else_set_engine(&context).await?;
+        if let Err(e) = self
+            .load_models(&ld.actor_id, &mut model_zoo, &settings)
+            .await
+        {
+            // the models loaded before the failure are not linked
+            self.unload_models(&ld.actor_id, &model_zoo).await;
+            return Err(e);
+        }
 
-            let graph: Graph = engine
-                .load(&model_data_bytes)
-                .await
-                .map_err(|error| RpcError::ProviderInit(format!("{}", error)))?;
+        let replaced = {
+            let mut actor_lock = self.actors.write().await;
+            actor_lock.insert(ld.actor_id.to_string(), model_zoo)
+        };
 
-            context.graph = graph;
+        // models of the previous link, those loaded again were already retired by their admission
+        if let Some(previous) = replaced {
+            self.unload_models(&ld.actor_id, &previous).await;
+        }
 
-            let gec: GraphExecutionContext = engine
-                .init_execution_context(
-                    context.graph,
-                    &context.execution_target,
-                    &context.graph_encoding,
-                )
-                .await
-                .map_err(|error| RpcError::ProviderInit(format!("{}", error)))?;
+        self.links
+            .write()
+            .await
+            .insert(ld.actor_id.to_string(), Arc::new(settings.clone()));
 
-            context.graph_execution_context = gec;
+        if let Some(recorder) = recorder {
+            let mut recorders_lock = self.recorders.write().await;
+            recorders_lock.insert(ld.actor_id.to_string(), Arc::new(recorder));
+        } else {
+            self.recorders.write().await.remove(&ld.actor_id);
         }
 
         {
-            let mut actor_lock = self.actors.write().await;
-            actor_lock.insert(ld.actor_id.to_string(), model_zoo);
+            let mut rate_limits_lock = self.rate_limits.write().await;
+            match settings.admission.rate_limit {
+                Some(limit) => {
+                    rate_limits_lock
+                        .insert(ld.actor_id.to_string(), Arc::new(TokenBucket::new(limit)));
+                }
+                None => {
+                    rate_limits_lock.remove(&ld.actor_id);
+                }
+            }
         }
 
         log::debug!("put_link_sub() - DONE");
@@ -207,6 +391,17 @@ impl MlInference for MlInferenceProvider {
         let model_name = &arg.model;
         let index = arg.index;
 
+        let rate_limit = self.rate_limits.read().await.get(&actor).cloned();
+        if let Some(bucket) = rate_limit {
+            if !bucket.try_take() {
+                let e = AdmissionError::RateLimited(actor.clone());
+                log::warn!("predict() - {}", e);
+                return Ok(get_default_inference_result(Some(MlError::RuntimeError(
+                    e.to_string(),
+                ))));
+            }
+        }
+
         let ar = self.actors.read().await;
         let modelzoo: &ModelZoo = match ar.get(&actor) {
             Some(v) => v,
@@ -229,172 +424,495 @@ impl MlInference for MlInferenceProvider {
             }
         };
 
-        let engine = self.get_engine(&model_context).await?;
+        // do not block link updates while waiting for admission
+        drop(ar);
+
+        let cache = model_context.cache.clone();
+        let cache_key = cache
+            .as_ref()
+            .map(|_| InferenceCache::key(&model_context.bindle_url, index, &arg.tensor));
+
+        if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+            if let Some(result) = cache.get(key) {
+                log::debug!(
+                    "predict() - cache hit for '{}' ({} hits, {} misses)",
+                    model_name,
+                    cache.hits(),
+                    cache.misses()
+                );
+                return Ok(result);
+            }
+        }
+
+        // the permit is held until the inference is done, even if it timed out
+        let permit = match model_context.gate.admit().await {
+            Ok(p) => p,
+            Err(e) => {
+                log::warn!("predict() - {}", e);
+                return Ok(get_default_inference_result(Some(MlError::RuntimeError(
+                    e.to_string(),
+                ))));
+            }
+        };
+
+        // the model is not evicted while in use, it is loaded if it is lazy or has been evicted
+        let in_use = model_context.residency.acquire();
+        let model_context = if model_context.residency.is_loaded() {
+            self.memory.touch(&(actor.clone(), model_name.clone()));
+            model_context
+        } else {
+            match self.reload(&actor, model_name, &model_context).await {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    log::error!("predict() - '{}' could not be loaded: {}", model_name, e);
+                    return Ok(get_default_inference_result(Some(MlError::RuntimeError(
+                        e.to_string(),
+                    ))));
+                }
+            }
+        };
+
+        let (engine, graph_execution_context) = match in_use.loaded() {
+            Some(Loaded {
+                engine,
+                graph_execution_context,
+                ..
+            }) => (engine, graph_execution_context),
+            None => {
+                log::error!("predict() - '{}' has been unlinked", model_name);
+                return Ok(get_default_inference_result(Some(
+                    MlError::ContextNotFoundError(model_name.clone()),
+                )));
+            }
+        };
+
+        let recorder = self.recorders.read().await.get(&actor).cloned();
+        let recording = recorder.filter(|r| r.sample()).map(|r| {
+            let record = Record {
+                timestamp_ms: Record::now(),
+                model: model_name.to_string(),
+                bindle_id: model_context.bindle_url.clone(),
+                engine: format!("{:?}", model_context.framework),
+                graph_encoding: model_context.graph_encoding.clone(),
+                execution_target: model_context.execution_target.clone(),
+                index,
+                input: arg.tensor.clone(),
+                output: get_default_inference_result(None),
+            };
+            (r, record)
+        });
 
         // it could be an expensive operation to clone the tensor,
         // but we hope (unconfirmed) the compiler will recognize that
         // the caller (dispatch fn) doesn't need it anymore and optimize out the clone.
         // TODO: confirm that this is true, or else find a way to make arg owned or Cow<'a>
         let tensor_in = arg.tensor.to_owned();
-        let result = tokio::task::spawn_blocking(move || async move {
-            if let Err(e) = engine
-                .set_input(model_context.graph_execution_context, index, &tensor_in)
-                .await
-            {
-                log::error!(
-                    "predict() - inference engine failed in 'set_input()' with '{}'",
-                    e
-                );
-                return get_default_inference_result(Some(MlError::ContextNotFoundError(
-                    e.to_string(),
-                )));
+        let monitor = model_context.monitor.clone();
+        let inference = tokio::task::spawn_blocking(move || {
+            // statistics walk the whole tensor, off the runtime and once the request is admitted
+            if let Some(monitor) = &monitor {
+                monitor.observe(&tensor_in);
             }
 
-            if let Err(e) = engine.compute(model_context.graph_execution_context).await {
-                log::error!("predict() - GraphExecutionContext not found: {}", e);
-                return get_default_inference_result(Some(MlError::ContextNotFoundError(
-                    e.to_string(),
-                )));
-            }
-            match engine
-                .get_output(model_context.graph_execution_context, index)
-                .await
-            {
-                Ok(result) => result,
-                Err(e) => {
-                    log::error!("predict() - could not gather results from 'get_output()'");
-                    get_default_inference_result(Some(MlError::ContextNotFoundError(e.to_string())))
+            async move {
+                let _permit = permit;
+                let _in_use = in_use;
+
+                if let Err(e) = engine
+                    .set_input(graph_execution_context, index, &tensor_in)
+                    .await
+                {
+                    log::error!(
+                        "predict() - inference engine failed in 'set_input()' with '{}'",
+                        e
+                    );
+                    return get_default_inference_result(Some(MlError::ContextNotFoundError(
+                        e.to_string(),
+                    )));
+                }
+
+                if let Err(e) = engine.compute(graph_execution_context).await {
+                    log::error!("predict() - GraphExecutionContext not found: {}", e);
+                    return get_default_inference_result(Some(MlError::ContextNotFoundError(
+                        e.to_string(),
+                    )));
+                }
+                match engine.get_output(graph_execution_context, index).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        log::error!("predict() - could not gather results from 'get_output()'");
+                        get_default_inference_result(Some(MlError::ContextNotFoundError(
+                            e.to_string(),
+                        )))
+                    }
                 }
             }
         })
         .await
-        .map_err(|e| RpcError::Other(format!("internal join error: {}", e)))?
-        .await;
+        .map_err(|e| RpcError::Other(format!("internal join error: {}", e)))?;
+
+        // a timed out inference keeps running in the background, its result is discarded
+        let result = match model_context.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, tokio::spawn(inference)).await {
+                Ok(joined) => {
+                    joined.map_err(|e| RpcError::Other(format!("internal join error: {}", e)))?
+                }
+                Err(_) => {
+                    log::warn!(
+                        "predict() - inference of '{}' timed out after {:?}",
+                        model_name,
+                        timeout
+                    );
+                    return Ok(get_default_inference_result(Some(MlError::RuntimeError(
+                        format!("inference timed out after {:?}", timeout),
+                    ))));
+                }
+            },
+            None => inference.await,
+        };
+
+        if let Some((recorder, mut record)) = recording {
+            record.output = result.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = recorder.record(&record) {
+                    log::warn!("predict() - recording failed: {}", e);
+                }
+            });
+        }
+
+        if let (Some(cache), Some(key)) = (cache, cache_key) {
+            if matches!(result.result, Status::Success) {
+                cache.put(key, result.clone());
+            }
+        }
 
         log::debug!("predict() - PASSED, result is '{:?}'", &result);
         Ok(result)
     }
 }
 
-impl MlInferenceProvider {
-    /// Each link definition may address a different target
-    /// such that it may be necessary to support multiple engines.
-    async fn get_or_else_set_engine(&mut self, context: &ModelContext) -> Result<Engine, RpcError> {
-        let mut engines_lock = self.engines.write().await;
-
-        match context.graph_encoding {
-            GraphEncoding::Onnx | GraphEncoding::Tensorflow => {
-                match engines_lock.get(&InferenceFramework::Tract) {
-                    Some(e) => {
-                        log::debug!("get_or_else_set_engine() - previously created Onnx/Tensorflow engine selected for '{}'.", context.bindle_url);
-                        Ok(e.clone())
-                    }
-                    None => {
-                        let feedback = engines_lock.insert(
-                            InferenceFramework::Tract,
-                            Arc::new(Box::new(TractEngine::default())),
-                        );
-                        log::debug!("get_or_else_set_engine() - Onnx/Tensorflow engine selected and created for '{}'.", context.bindle_url);
-
-                        if feedback.is_none() {
-                            log::debug!(
-                                "get_or_else_set_engine() - Onnx/Tensorflow was definitely created"
-                            );
-                        } else {
-                            log::debug!(
-                                "get_or_else_set_engine() - Onnx/Tensorflow was NOT created!"
-                            );
-                        }
-
-                        log::debug!(
-                            "get_or_else_set_engine() - content of Engines: {:?}: with length {:?}",
-                            &engines_lock.keys(),
-                            &engines_lock.keys().len()
-                        );
-
-                        //assert_eq!(engines_lock.is_empty(), false);
-
-                        Ok(engines_lock
-                            .get(&InferenceFramework::Tract)
-                            .unwrap()
-                            .clone())
-                    }
-                }
-            }
+/// Handle MlLabels methods
+#[async_trait]
+impl MlLabels for MlInferenceProvider {
+    /// labels of a model linked to the calling actor
+    async fn get_labels(&self, ctx: &Context, arg: &LabelsRequest) -> RpcResult<Labels> {
+        let actor = ctx
+            .actor
+            .as_ref()
+            .ok_or_else(|| RpcError::InvalidParameter("get_labels() - no actor".to_string()))?;
 
-            #[cfg(any(feature = "tflite", feature = "edgetpu"))]
-            GraphEncoding::TfLite => {
-                match engines_lock.get(&InferenceFramework::TfLite) {
-                    Some(e) => {
-                        log::debug!("get_or_else_set_engine() - previously created TfLite engine selected for '{}'.", context.bindle_url);
-                        Ok(e.clone())
-                    }
-                    None => {
-                        let feedback = engines_lock.insert(
-                            InferenceFramework::TfLite,
-                            Arc::new(Box::new(TfLiteEngine::default())),
-                        );
-                        log::debug!("get_or_else_set_engine() - TfLite engine selected and created for '{}'.", context.bindle_url);
-
-                        if feedback.is_none() {
-                            log::debug!(
-                                "get_or_else_set_engine() - TfLite engine was definitely created"
-                            );
-                        } else {
-                            log::debug!("get_or_else_set_engine() - TfLite was NOT created!");
-                        }
-
-                        log::debug!(
-                            "get_or_else_set_engine() - content of Engines: {:?}: with length {:?}",
-                            &engines_lock.keys(),
-                            &engines_lock.keys().len()
-                        );
-
-                        Ok(engines_lock
-                            .get(&InferenceFramework::TfLite)
-                            .unwrap()
-                            .clone())
-                    }
-                }
-            }
-            _ => {
+        let ar = self.actors.read().await;
+        let model_context = ar
+            .get(actor)
+            .and_then(|modelzoo| modelzoo.get(&arg.model))
+            .ok_or_else(|| {
                 log::error!(
-                    "unsupported graph encoding detected '{:?}'",
-                    &context.graph_encoding
+                    "get_labels() - model '{}' of actor {} not found",
+                    &arg.model,
+                    actor
                 );
-                Err(RpcError::NotImplemented)
+                RpcError::InvalidParameter(format!("model '{}' not found", arg.model))
+            })?;
+
+        model_context
+            .labels
+            .as_ref()
+            .map(|labels| labels.to_vec())
+            .ok_or_else(|| {
+                RpcError::InvalidParameter(format!(
+                    "model '{}' has no parcel of group '{}'",
+                    arg.model, LABELS_GROUP
+                ))
+            })
+    }
+}
+
+impl MlInferenceProvider {
+    /// Loads and initializes a model on the first available of 'targets',
+    /// recording the framework and effective target in the model's context.
+    async fn initialize_model(
+        &self,
+        context: &mut ModelContext,
+        targets: &[ExecutionTarget],
+        preferred: Option<&InferenceFramework>,
+        metadata: &ModelMetadata,
+        model: &[u8],
+        cpu_variant: Option<&[u8]>,
+    ) -> Result<Engine, RpcError> {
+        let initialized = self
+            .engines
+            .initialize(targets, preferred, metadata, model, cpu_variant)
+            .await
+            .map_err(|e| {
+                log::error!("initialize_model() - '{}': {}", context.bindle_url, e);
+                RpcError::ProviderInit(e.to_string())
+            })?;
+
+        if initialized.execution_target != targets[0] {
+            log::warn!(
+                "initialize_model() - '{}' falls back from target '{:?}' to '{:?}'",
+                context.bindle_url,
+                targets[0],
+                initialized.execution_target
+            );
+        }
+        log::info!(
+            "initialize_model() - '{}' runs on framework '{:?}', target '{:?}'",
+            context.bindle_url,
+            initialized.framework,
+            initialized.execution_target
+        );
+
+        context.framework = initialized.framework;
+        context.execution_target = initialized.execution_target;
+        context.graph = initialized.graph;
+        context.graph_execution_context = initialized.graph_execution_context;
+
+        Ok(initialized.engine)
+    }
+
+    /// Downloads and initializes a model, admitting it to the memory budget.
+    /// Returns its engine and the source of its parcels.
+    async fn load_model(
+        &self,
+        actor: &str,
+        name: &str,
+        context: &mut ModelContext,
+        settings: &ModelSettings,
+    ) -> Result<(Engine, Box<dyn ModelLoader>), RpcError> {
+        // options of the model take precedence over those of the link
+        let options = settings.models.zoo[name].options();
+
+        // models are pulled from the bindle server or an OCI registry by their scheme
+        let (scheme, id) = split_reference(&context.bindle_url);
+        let id = id.to_string();
+        let loader = model_loader(scheme, settings).map_err(|error| {
+            log::error!("model_loader() failed for '{}'!", context.bindle_url);
+            RpcError::ProviderInit(format!("{}", error))
+        })?;
+
+        // the targets to try select the model's variant, the first supported by an engine
+        let mut targets: Vec<ExecutionTarget> = options
+            .execution_target
+            .iter()
+            .cloned()
+            .chain(options.fallback_targets.unwrap_or_default())
+            .collect();
+        let configured = !targets.is_empty();
+        let preference = VariantPreference {
+            targets: targets.clone(),
+            precision: options.precision,
+        };
+        let link_preferred = options
+            .preferred_framework
+            .clone()
+            .or_else(|| settings.preferred_framework.clone());
+
+        let downloads = BindleLoader::get_model_and_metadata(
+            loader.as_ref(),
+            &id,
+            &preference,
+            |encoding, target| {
+                self.engines
+                    .select(encoding, target, link_preferred.as_ref())
+                    .is_ok()
+            },
+        )
+        .await
+        .map_err(|error| {
+            log::error!("get_model_and_metadata() failed!");
+            RpcError::ProviderInit(format!("{}", error))
+        })?;
+
+        let (mut metadata, model_data_bytes) = downloads;
+
+        context.load_metadata(metadata.clone()).map_err(|error| {
+            log::error!("load_metadata() failed!");
+            RpcError::InvalidParameter(format!("{:?}", error))
+        })?;
+
+        // the selected variant's target opens the chain, unless the model's options name another
+        match targets.iter().position(|t| *t == context.execution_target) {
+            Some(position) => {
+                targets.drain(..position);
+            }
+            None if options.execution_target.is_none() => {
+                targets.insert(0, context.execution_target.clone())
             }
+            None => (),
         }
+        // without configured targets, a variant selected for an accelerator falls back to the CPU
+        if !configured && !targets.contains(&ExecutionTarget::Cpu) {
+            targets.push(ExecutionTarget::Cpu);
+        }
+        context.execution_target = targets[0].clone();
+        context.timeout = options.timeout_ms.map(Duration::from_millis);
+
+        // options of the link take precedence over those of the model's metadata
+        metadata.tflite = Some(
+            TfLiteOptions {
+                threads: options.threads,
+                ..Default::default()
+            }
+            .or(settings.tflite.clone())
+            .or(metadata.tflite.take().unwrap_or_default()),
+        );
+
+        // each link definition may address a different target
+        // such that it may be necessary to support multiple engines.
+        let preferred = link_preferred.or_else(|| metadata.preferred_framework.clone());
+
+        // a model compiled for the Edge TPU needs a CPU variant to fall back to
+        let cpu_variant = if targets.len() > 1 && requires_edge_tpu(&model_data_bytes) {
+            BindleLoader::get_cpu_variant(loader.as_ref(), &id)
+                .await
+                .map_err(|error| RpcError::ProviderInit(format!("{}", error)))?
+        } else {
+            None
+        };
+
+        let engine = self
+            .initialize_model(
+                context,
+                &targets,
+                preferred.as_ref(),
+                &metadata,
+                &model_data_bytes,
+                cpu_variant.as_deref(),
+            )
+            .await?;
+
+        // the raw model is released once this returns, its size stands for its graph and session
+        let bytes = model_data_bytes.len() + cpu_variant.as_ref().map_or(0, Vec::len);
+        let evicted = self.memory.admit(
+            (actor.to_string(), name.to_string()),
+            &context.residency,
+            Loaded {
+                engine: engine.clone(),
+                graph: context.graph,
+                graph_execution_context: context.graph_execution_context,
+                bytes: bytes as u64,
+            },
+        );
+        log::info!(
+            "load_model() - '{}' accounts for {} bytes, {} bytes of models are loaded",
+            context.bindle_url,
+            bytes,
+            self.memory.used()
+        );
+        drop_evicted(evicted).await;
+
+        Ok((engine, loader))
     }
 
-    async fn get_engine(&self, context: &ModelContext) -> Result<Engine, RpcError> {
-        let engines_lock = self.engines.write().await;
+    /// Loads a lazy or evicted model by the settings of its link, returning its updated context
+    async fn reload(
+        &self,
+        actor: &str,
+        name: &str,
+        context: &ModelContext,
+    ) -> Result<ModelContext, RpcError> {
+        // concurrent inferences of a model load it once
+        let reloading = self
+            .reloads
+            .lock()
+            .unwrap()
+            .entry((actor.to_string(), name.to_string()))
+            .or_default()
+            .clone();
+        let _reloading = reloading.lock().await;
+        if context.residency.is_loaded() {
+            return Ok(self
+                .actors
+                .read()
+                .await
+                .get(actor)
+                .and_then(|model_zoo| model_zoo.get(name))
+                .cloned()
+                .unwrap_or_else(|| context.clone()));
+        }
 
-        log::debug!("get_engine() - context: {:?}", &context);
+        let settings = self.links.read().await.get(actor).cloned().ok_or_else(|| {
+            RpcError::InvalidParameter(format!("reload() - actor {} is not linked", actor))
+        })?;
+
+        let start = std::time::Instant::now();
+        let mut reloaded = context.clone();
+        let (_, loader) = self
+            .load_model(actor, name, &mut reloaded, &settings)
+            .await?;
+        self.attach_model(name, &mut reloaded, loader.as_ref(), &settings)
+            .await?;
+        log::info!(
+            "reload() - '{}' of actor {} loaded in {:?}",
+            context.bindle_url,
+            actor,
+            start.elapsed()
+        );
 
-        match context.graph_encoding {
-            GraphEncoding::Onnx | GraphEncoding::Tensorflow => {
-                match engines_lock.get(&InferenceFramework::Tract) {
-                    Some(e) => Ok(e.clone()),
-                    None => Err(RpcError::InvalidParameter(
-                        "get_engine() - No engine found for graph encoding 'Onnx' or 'Tensorflow'"
-                            .to_string(),
-                    )),
-                }
+        // the link may have been deleted meanwhile
+        let linked = match self
+            .actors
+            .write()
+            .await
+            .get_mut(actor)
+            .and_then(|model_zoo| model_zoo.get_mut(name))
+        {
+            Some(c) if Arc::ptr_eq(&c.residency, &context.residency) => {
+                *c = reloaded.clone();
+                true
             }
-            GraphEncoding::TfLite => match engines_lock.get(&InferenceFramework::TfLite) {
-                Some(e) => Ok(e.clone()),
-                None => Err(RpcError::InvalidParameter(
-                    "get_engine() - No engine found for graph encoding 'TfLite'".to_string(),
-                )),
-            },
-            _ => {
-                log::error!(
-                    "get_engine() - unsupported graph encoding detected '{:?}'",
-                    &context.graph_encoding
+            _ => false,
+        };
+        if !linked {
+            if let Some(loaded) = context.residency.retire() {
+                loaded
+                    .engine
+                    .drop_model_state(&loaded.graph, &loaded.graph_execution_context)
+                    .await;
+            }
+            self.memory
+                .release(&(actor.to_string(), name.to_string()), &context.residency);
+        }
+
+        Ok(reloaded)
+    }
+
+    /// Attaches the result cache and input monitor of a loaded model, unless it has them
+    async fn attach_model(
+        &self,
+        name: &str,
+        context: &mut ModelContext,
+        loader: &dyn ModelLoader,
+        settings: &ModelSettings,
+    ) -> Result<(), RpcError> {
+        // options of the model take precedence over those of the link
+        let cache = settings.models.zoo[name]
+            .options()
+            .cache
+            .unwrap_or_else(|| settings.cache.clone());
+        if context.cache.is_none() && cache.is_enabled() && !context.non_deterministic {
+            context.cache = Some(Arc::new(InferenceCache::new(cache)));
+        }
+
+        if context.monitor.is_none() && settings.monitoring.enabled {
+            let id = split_reference(&context.bindle_url).1;
+            let profile = BindleLoader::get_profile(loader, id)
+                .await
+                .map_err(|error| RpcError::ProviderInit(format!("{}", error)))?;
+            if profile.is_none() {
+                log::info!(
+                    "no input profile for '{}', drift is not checked",
+                    context.bindle_url
                 );
-                Err(RpcError::NotImplemented)
             }
+            context.monitor = Some(Arc::new(InputMonitor::new(
+                &context.bindle_url,
+                settings.monitoring.clone(),
+                profile,
+            )));
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,179 @@
+//! converts ONNX and Tensorflow models to NNEF and packages them as bindle
+//!
+//! The model is typed and decluttered by tract with the given input facts,
+//! checked to be optimizable and written as '.nnef.tgz'. Next to it, the
+//! metadata and the bindle invoice are written, with metadata inferred from
+//! the model and parcels hashed from the written files.
+//!
+//! usage: mlinference-pack model.onnx --input 1,3,224,224,f32 --version 0.1.0 --out ../../bindle/models
+//!
+use clap::Parser;
+use flate2::{write::GzEncoder, Compression};
+use std::{fs::File, path::PathBuf};
+use tract_onnx::{prelude::*, WithOnnx};
+use wasmcloud_provider_mlinference::{
+    invoice_toml, tensor_type, ExecutionTarget, GraphEncoding, InvoiceSpec, ModelMetadata,
+    ParcelFile, METADATA_GROUP, MODEL_GROUP,
+};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "mlinference-pack",
+    about = "Converts a model to NNEF and packages it as bindle"
+)]
+struct Args {
+    /// ONNX (.onnx) or Tensorflow (.pb) model
+    model: PathBuf,
+
+    /// input fact as comma separated dimensions followed by an optional type,
+    /// e.g. '1,3,224,224,f32', repeated in order of the model's inputs
+    #[clap(short, long = "input")]
+    inputs: Vec<String>,
+
+    /// bindle name, defaults to the model's file name
+    #[clap(long)]
+    name: Option<String>,
+
+    /// bindle version
+    #[clap(long, default_value = "0.1.0")]
+    version: String,
+
+    /// bindle author, may be repeated
+    #[clap(long = "author")]
+    authors: Vec<String>,
+
+    /// bindle description
+    #[clap(long)]
+    description: Option<String>,
+
+    /// directory the NNEF model, metadata and invoice are written to
+    #[clap(short, long, default_value = ".")]
+    out: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let stem = args
+        .model
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .ok_or("model path has no file name")?;
+    let name = args.name.clone().unwrap_or_else(|| stem.clone());
+
+    let mut model = match args.model.extension().and_then(|e| e.to_str()) {
+        Some("onnx") => tract_onnx::onnx().model_for_path(&args.model)?,
+        Some("pb") => tract_tensorflow::tensorflow().model_for_path(&args.model)?,
+        _ => {
+            return Err(format!(
+                "cannot tell the format of '{}', expecting '.onnx' or '.pb'",
+                args.model.display()
+            )
+            .into())
+        }
+    };
+
+    for (index, input) in args.inputs.iter().enumerate() {
+        model.set_input_fact(index, parse_fact(input)?)?;
+    }
+
+    println!("typing and decluttering '{}' ..", args.model.display());
+    let model = model.into_typed()?.into_decluttered()?;
+    model.clone().into_optimized()?;
+
+    let metadata = infer_metadata(&model, &name)?;
+    if metadata.tensor_dimensions_in.is_none() {
+        println!("input dimensions are not fully known, consider pinning them with '--input'");
+    }
+
+    std::fs::create_dir_all(&args.out)?;
+
+    let model_path = args.out.join(format!("{}.nnef.tgz", stem));
+    let encoder = GzEncoder::new(File::create(&model_path)?, Compression::default());
+    tract_nnef::nnef()
+        .with_tract_core()
+        .with_onnx()
+        .write_to_tar(&model, encoder)?
+        .finish()?;
+    println!("wrote '{}'", model_path.display());
+
+    let metadata_path = args.out.join(format!("{}.json", stem));
+    std::fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?)?;
+    println!("wrote '{}'", metadata_path.display());
+
+    let parcels = vec![
+        ParcelFile::from_path(&model_path, "application/octet-stream", &[MODEL_GROUP])?,
+        ParcelFile::from_path(&metadata_path, "application/json", &[METADATA_GROUP])?,
+    ];
+    let spec = InvoiceSpec {
+        name,
+        version: args.version,
+        authors: args.authors,
+        description: args.description,
+    };
+
+    let invoice_path = args.out.join(format!("{}.toml", stem));
+    std::fs::write(&invoice_path, invoice_toml(&spec, &parcels)?)?;
+    println!("wrote '{}'", invoice_path.display());
+
+    Ok(())
+}
+
+/// parses e.g. '1,3,224,224,f32' into an input fact, defaulting to f32
+fn parse_fact(fact: &str) -> Result<InferenceFact, String> {
+    let mut tokens: Vec<&str> = fact.split(',').map(str::trim).collect();
+
+    let datum_type = match tokens.last().map(|t| t.parse::<usize>().is_err()) {
+        Some(true) => parse_datum_type(tokens.pop().unwrap())?,
+        _ => f32::datum_type(),
+    };
+
+    let shape = tokens
+        .iter()
+        .map(|t| t.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|e| format!("invalid dimension in input fact '{}': {}", fact, e))?;
+
+    Ok(InferenceFact::dt_shape(datum_type, shape))
+}
+
+fn parse_datum_type(datum_type: &str) -> Result<DatumType, String> {
+    match datum_type.to_lowercase().as_str() {
+        "f32" => Ok(f32::datum_type()),
+        "f64" => Ok(f64::datum_type()),
+        "u8" => Ok(u8::datum_type()),
+        "i8" => Ok(i8::datum_type()),
+        "i32" => Ok(i32::datum_type()),
+        "i64" => Ok(i64::datum_type()),
+        _ => Err(format!("unsupported input type '{}'", datum_type)),
+    }
+}
+
+/// metadata as declared by the model's first input and output
+fn infer_metadata(model: &TypedModel, name: &str) -> TractResult<ModelMetadata> {
+    let input = model.input_fact(0)?;
+    let output = model.output_fact(0)?;
+
+    let dimensions = |fact: &TypedFact| {
+        fact.shape
+            .as_concrete()
+            .map(|dims| dims.iter().map(|&d| d as u32).collect::<Vec<u32>>())
+    };
+
+    let tensor_type = tensor_type(input.datum_type).ok_or_else(|| {
+        TractError::msg(format!(
+            "input type {:?} has no tensor type of the interface",
+            input.datum_type
+        ))
+    })?;
+
+    Ok(ModelMetadata {
+        model_name: Some(name.to_string()),
+        graph_encoding: GraphEncoding::Nnef,
+        execution_target: ExecutionTarget::Cpu,
+        tensor_type,
+        tensor_dimensions_in: dimensions(input),
+        tensor_dimensions_out: dimensions(output),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,163 @@
+//! replays a recording of the mlinference provider
+//!
+//! Each recorded input is run against the engines of this build, using the
+//! model bindles referenced by the recording as found at 'BINDLE_URL', or the
+//! OCI artifacts of recorded 'oci://' references, pulled by https.
+//! Differences are reported per output tensor. Invoices are verified against
+//! the keyring given by '--keyring', unsigned ones are only replayed with
+//! '--allow-unsigned'.
+//!
+//! usage: replay [--keyring <keyring.toml>] [--allow-unsigned] <recording.mlrec> [tolerance]
+//!
+use std::{collections::HashMap, path::PathBuf};
+use wasmcloud_provider_mlinference::{
+    model_loader, read_recording, split_reference, BindleLoader, Engine, EngineRegistry,
+    GraphExecutionContext, ModelContext, ModelSettings, Record, TensorDiff, VariantPreference,
+};
+
+/// default maximum absolute difference of f32 values
+const DEFAULT_TOLERANCE: f32 = 1e-5;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| "replay".to_string());
+    let usage = || {
+        eprintln!(
+            "usage: {} [--keyring <keyring.toml>] [--allow-unsigned] <recording.mlrec> [tolerance]",
+            program
+        );
+        std::process::exit(2);
+    };
+
+    let mut settings = ModelSettings::default();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyring" => match args.next() {
+                Some(path) => settings.signatures.keyring = Some(PathBuf::from(path)),
+                None => usage(),
+            },
+            "--allow-unsigned" => settings.signatures.allow_unsigned = true,
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() || positional.len() > 2 {
+        usage();
+    }
+
+    let tolerance: f32 = match positional.get(1) {
+        Some(t) => t.parse()?,
+        None => DEFAULT_TOLERANCE,
+    };
+
+    let records = read_recording(&positional[0])?;
+    println!(
+        "replaying {} records from '{}'",
+        records.len(),
+        positional[0]
+    );
+
+    let registry = EngineRegistry::default();
+    let mut models: HashMap<String, (Engine, GraphExecutionContext)> = HashMap::new();
+    let mut deviating = 0;
+
+    for (i, record) in records.iter().enumerate() {
+        if !models.contains_key(&record.bindle_id) {
+            // the variant of the recorded target is replayed
+            let preference = VariantPreference {
+                targets: vec![record.execution_target.clone()],
+                ..Default::default()
+            };
+            let (scheme, id) = split_reference(&record.bindle_id);
+            let loader = model_loader(scheme, &settings)?;
+            let (metadata, model_data_bytes) = BindleLoader::get_model_and_metadata(
+                loader.as_ref(),
+                id,
+                &preference,
+                |encoding, target| registry.select(encoding, target, None).is_ok(),
+            )
+            .await?;
+
+            let mut context = ModelContext {
+                bindle_url: record.bindle_id.clone(),
+                ..ModelContext::default()
+            };
+            context
+                .load_metadata(metadata.clone())
+                .map_err(|e| format!("invalid metadata of '{}': {:?}", record.bindle_id, e))?;
+
+            let (_, engine) = registry.select(
+                &context.graph_encoding,
+                &context.execution_target,
+                metadata.preferred_framework.as_ref(),
+            )?;
+            let graph = engine.load(&model_data_bytes).await?;
+            engine.configure(graph, &metadata).await?;
+            let gec = engine
+                .init_execution_context(graph, &context.execution_target, &context.graph_encoding)
+                .await?;
+
+            models.insert(record.bindle_id.clone(), (engine, gec));
+        }
+
+        let (engine, gec) = &models[&record.bindle_id];
+        let diff = replay(engine, *gec, record).await;
+
+        match diff {
+            Ok(diff) if diff.within(tolerance) => {
+                println!(
+                    "#{} '{}' [{}]: ok, {}",
+                    i, record.model, record.engine, diff
+                );
+            }
+            Ok(diff) => {
+                deviating += 1;
+                println!(
+                    "#{} '{}' [{}]: DEVIATES, {}",
+                    i, record.model, record.engine, diff
+                );
+            }
+            Err(e) => {
+                deviating += 1;
+                println!(
+                    "#{} '{}' [{}]: FAILED, {}",
+                    i, record.model, record.engine, e
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} of {} records deviate with tolerance {:e}",
+        deviating,
+        records.len(),
+        tolerance
+    );
+
+    if deviating > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// runs a single record, comparing the result to the recorded one
+async fn replay(
+    engine: &Engine,
+    gec: GraphExecutionContext,
+    record: &Record,
+) -> Result<TensorDiff, Box<dyn std::error::Error>> {
+    engine.set_input(gec, record.index, &record.input).await?;
+    engine.compute(gec).await?;
+    let output = engine.get_output(gec, record.index).await?;
+
+    if output.result != record.output.result {
+        return Err(format!(
+            "status differs: recorded {:?}, replayed {:?}",
+            record.output.result, output.result
+        )
+        .into());
+    }
+
+    Ok(TensorDiff::between(&record.output.tensor, &output.tensor))
+}
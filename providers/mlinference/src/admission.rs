@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use thiserror::Error as ThisError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits applied to a single model of a link.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ModelLimits {
+    /// maximum number of concurrently running inferences (unbounded if not set)
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+
+    /// maximum number of requests waiting for a free slot
+    #[serde(default)]
+    pub queue_depth: Option<usize>,
+}
+
+/// Token bucket parameters of a per-actor rate limit.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RateLimit {
+    /// sustained number of requests per second
+    pub requests_per_second: f64,
+
+    /// number of requests which may be issued in a burst
+    #[serde(default = "RateLimit::default_burst")]
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn default_burst() -> u32 {
+        1
+    }
+}
+
+impl Eq for RateLimit {}
+
+/// Admission control settings of a link.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AdmissionSettings {
+    /// default limits of all models of the link
+    #[serde(flatten)]
+    pub limits: ModelLimits,
+
+    /// per-actor rate limit (unlimited if not set)
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+
+    /// model specific limits overriding the defaults
+    #[serde(default)]
+    pub models: HashMap<crate::ModelName, ModelLimits>,
+}
+
+impl AdmissionSettings {
+    /// limits of a given model, falling back to the link's defaults
+    pub fn limits_for(&self, model: &str) -> ModelLimits {
+        let specific = self.models.get(model);
+        ModelLimits {
+            max_in_flight: specific
+                .and_then(|l| l.max_in_flight)
+                .or(self.limits.max_in_flight),
+            queue_depth: specific
+                .and_then(|l| l.queue_depth)
+                .or(self.limits.queue_depth),
+        }
+    }
+}
+
+/// AdmissionResult
+pub type AdmissionResult<T> = Result<T, AdmissionError>;
+
+#[derive(Debug, ThisError, PartialEq, Eq)]
+pub enum AdmissionError {
+    #[error("model '{0}' is overloaded, request rejected")]
+    Overloaded(String),
+
+    #[error("actor '{0}' exceeded its rate limit, request rejected")]
+    RateLimited(String),
+}
+
+/// Bounds the number of running and queued inferences of a model.
+#[derive(Debug)]
+pub struct ModelGate {
+    model: String,
+    limits: ModelLimits,
+    slots: Option<Arc<Semaphore>>,
+    queued: AtomicUsize,
+}
+
+impl ModelGate {
+    pub fn new(model: &str, limits: ModelLimits) -> Self {
+        Self {
+            model: model.to_string(),
+            slots: limits.max_in_flight.map(|n| Arc::new(Semaphore::new(n))),
+            limits,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits for a free slot. Rejects immediately if the queue is full.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn admit(&self) -> AdmissionResult<Option<OwnedSemaphorePermit>> {
+        let slots = match &self.slots {
+            Some(s) => s.clone(),
+            None => return Ok(None),
+        };
+
+        if let Ok(permit) = slots.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        let queue_depth = self.limits.queue_depth.unwrap_or(0);
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= queue_depth {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            log::warn!(
+                "admit() - rejecting request for model '{}': {} in flight, queue of {} is full",
+                self.model,
+                self.limits.max_in_flight.unwrap_or_default(),
+                queue_depth
+            );
+            return Err(AdmissionError::Overloaded(self.model.clone()));
+        }
+
+        // also leaves the queue if the request is dropped while waiting
+        let _queued = Queued(&self.queued);
+        let permit = slots.acquire_owned().await;
+
+        // the semaphore is never closed
+        permit
+            .map(Some)
+            .map_err(|_| AdmissionError::Overloaded(self.model.clone()))
+    }
+
+    /// number of requests currently waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn limits(&self) -> &ModelLimits {
+        &self.limits
+    }
+}
+
+/// A request waiting for a slot, counted by `ModelGate::queued` until dropped
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl PartialEq for ModelGate {
+    fn eq(&self, other: &Self) -> bool {
+        self.model == other.model && self.limits == other.limits
+    }
+}
+
+impl Default for ModelGate {
+    fn default() -> Self {
+        ModelGate::new("", ModelLimits::default())
+    }
+}
+
+/// Classic token bucket, refilled continuously.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        let tokens = limit.burst as f64;
+        Self {
+            limit,
+            state: Mutex::new((tokens, Instant::now())),
+        }
+    }
+
+    /// takes a token if one is available
+    pub fn try_take(&self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.limit.requests_per_second).min(self.limit.burst as f64);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let bucket = TokenBucket::new(RateLimit {
+            requests_per_second: 2.0,
+            burst: 2,
+        });
+        let start = Instant::now();
+
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start), "burst exhausted");
+        assert!(bucket.try_take_at(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take_at(start + Duration::from_millis(600)));
+    }
+
+    #[tokio::test]
+    async fn gate_rejects_when_queue_is_full() {
+        let gate = ModelGate::new(
+            "m",
+            ModelLimits {
+                max_in_flight: Some(1),
+                queue_depth: Some(0),
+            },
+        );
+
+        let permit = gate.admit().await.unwrap();
+        assert!(permit.is_some());
+        assert_eq!(
+            gate.admit().await.unwrap_err(),
+            AdmissionError::Overloaded("m".to_string())
+        );
+
+        drop(permit);
+        assert!(gate.admit().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn dropped_requests_leave_the_queue() {
+        let gate = ModelGate::new(
+            "m",
+            ModelLimits {
+                max_in_flight: Some(1),
+                queue_depth: Some(1),
+            },
+        );
+
+        let _permit = gate.admit().await.unwrap();
+        let waiting = tokio::time::timeout(Duration::from_millis(10), gate.admit()).await;
+        assert!(waiting.is_err(), "no slot is free");
+
+        assert_eq!(gate.queued(), 0);
+        let queued_again = tokio::time::timeout(Duration::from_millis(10), gate.admit()).await;
+        assert!(queued_again.is_err(), "queued rather than rejected");
+    }
+
+    #[test]
+    fn model_limits_override_defaults() {
+        let settings: AdmissionSettings = serde_json::from_str(
+            r#"{"max_in_flight": 4, "queue_depth": 8, "models": {"a": {"max_in_flight": 1}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings.limits_for("a"),
+            ModelLimits {
+                max_in_flight: Some(1),
+                queue_depth: Some(8)
+            }
+        );
+        assert_eq!(settings.limits_for("b"), settings.limits);
+    }
+}
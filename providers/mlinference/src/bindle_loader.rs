@@ -1,13 +1,18 @@
 //use crate::{BindlePath};
-use crate::{ExecutionTarget, GraphEncoding};
+use crate::{
+    complete_metadata, select_variant, Dimension, DownloadSettings, Downloader, ExecutionTarget,
+    GraphEncoding, InferenceFramework, InputProfile, ModelLoader, ModelVariant, TfLiteOptions,
+    VariantPreference, CPU_VARIANT_GROUP, LABELS_GROUP, METADATA_GROUP, MODEL_ANNOTATION,
+    MODEL_GROUP,
+};
 use bindle::client::{tokens::NoToken, Client};
 use serde::{Deserialize, Serialize};
 use thiserror::Error as ThisError;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ModelMetadata {
     /// Model name (optional)
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub model_name: Option<String>,
 
     /// graph encoding
@@ -18,20 +23,65 @@ pub struct ModelMetadata {
     #[serde(default)]
     pub execution_target: ExecutionTarget,
 
+    /// framework to run the model on rather than the one of the highest priority (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_framework: Option<InferenceFramework>,
+
     /// tensor type
     #[serde(default)]
     pub tensor_type: String,
 
     /// tensor dimensions in (optional)
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tensor_dimensions_in: Option<Vec<u32>>,
 
     /// tensor dimensions out (optional)
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tensor_dimensions_out: Option<Vec<u32>>,
+
+    /// input shape of fixed and symbolic dimensions, e.g. ["N", 3, 224, 224], lets tract
+    /// optimize the model once for any size of the symbolic dimensions (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tensor_shape_in: Option<Vec<Dimension>>,
+
+    /// shapes of several inputs by their index, e.g. of token ids and attention mask
+    /// [["N", "S"], ["N", "S"]], in place of 'tensor_shape_in' (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tensor_shapes_in: Option<Vec<Vec<Dimension>>>,
+
+    /// options of the TfLite interpreter (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tflite: Option<TfLiteOptions>,
+
+    /// results may differ for identical inputs, excludes the model from caching
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub non_deterministic: bool,
+
+    /// architecture the weights are loaded into, required for 'safetensors' (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+
+    /// architecture specific configuration (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture_config: Option<serde_json::Value>,
+
+    /// labels of the output classes, read from the parcel of group 'labels' (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+
+    /// creator of the model's invoice as verified by its signature, None if unsigned
+    #[serde(skip)]
+    pub signer: Option<String>,
 }
 
 impl ModelMetadata {
+    /// input shapes by index, 'tensor_shapes_in' or 'tensor_shape_in' of the first input
+    pub fn input_shapes(&self) -> Option<Vec<Vec<Dimension>>> {
+        self.tensor_shapes_in
+            .clone()
+            .or_else(|| self.tensor_shape_in.clone().map(|shape| vec![shape]))
+    }
+
     /// load metadata from json
     pub fn from_json(data: &[u8]) -> Result<Self, Error> {
         serde_json::from_slice(data)
@@ -65,74 +115,230 @@ impl BindleLoader {
         Ok(bindle_client)
     }
 
-    /// get model and metadata
-    pub async fn get_model_and_metadata(
-        bindle_client: &Client<NoToken>,
-        bindle_url: &str,
-    ) -> BindleResult<(ModelMetadata, Vec<u8>)> {
-        let invoice = bindle_client.get_invoice(bindle_url).await.map_err(|_| {
-            log::error!("Bindle Invoice not found!");
-            BindleError::BindleInvoiceNotFoundError(bindle_url.to_string())
+    /// downloader from the bindle server of the url in the environment variable 'bindle_url'
+    pub fn downloader(bindle_url: &str, settings: DownloadSettings) -> BindleResult<Downloader> {
+        let url = std::env::var(bindle_url).map_err(|_| {
+            log::error!("No Bindle Url defined!");
+            BindleError::NoBindleUrlDefinedError
         })?;
 
-        let parcels = invoice.parcel.ok_or_else(|| {
-            log::error!("Bindle Parcel not found!");
-            BindleError::BindleParcelNotFoundError(bindle_url.to_string())
-        })?;
+        Downloader::new(&url, settings)
+    }
 
-        let model_parcel = BindleLoader::get_first_member_of(&parcels, "model").map_err(|_| {
+    /// get model and metadata of a model's parcels listed by the 'loader', the model being the
+    /// variant selected by 'preference' among those an engine 'supports', the metadata the one
+    /// describing it or else the shared one
+    pub async fn get_model_and_metadata<F>(
+        loader: &dyn ModelLoader,
+        bindle_url: &str,
+        preference: &VariantPreference,
+        supports: F,
+    ) -> BindleResult<(ModelMetadata, Vec<u8>)>
+    where
+        F: Fn(&GraphEncoding, &ExecutionTarget) -> bool,
+    {
+        let (parcels, signer) = loader.get_parcels(bindle_url).await?;
+
+        if BindleLoader::get_members_of(&parcels, "model").is_empty() {
             log::error!("No Bindle Parcel of group 'model'!");
-            BindleError::BindleNoParcelOfGroupModelError
-        })?;
+            return Err(BindleError::BindleNoParcelOfGroupModelError);
+        }
+
+        let (model_parcel, variant) =
+            select_variant(&parcels, preference, supports).ok_or_else(|| {
+                log::error!(
+                    "No model variant of '{}' matches {:?}!",
+                    bindle_url,
+                    preference
+                );
+                BindleError::BindleNoMatchingVariantError(bindle_url.to_string())
+            })?;
+        log::info!(
+            "selected model variant '{}' of '{}': {:?}",
+            model_parcel.label.name,
+            bindle_url,
+            variant
+        );
 
         let metadata_parcel =
-            BindleLoader::get_first_member_of(&parcels, "metadata").map_err(|_| {
-                log::error!("No Bindle Parcel of group 'metadata'!");
-                BindleError::BindleNoParcelOfGroupMetadataError
-            })?;
+            BindleLoader::get_member_describing(&parcels, METADATA_GROUP, model_parcel);
+        let labels_parcel =
+            BindleLoader::get_member_describing(&parcels, LABELS_GROUP, model_parcel);
 
-        let model_data_blob: Vec<u8> = bindle_client
-            .get_parcel(bindle_url, &model_parcel.label.sha256)
-            .await
-            .map_err(|_| {
-                log::error!("Bindle Parcel 'model' could not be fetched!");
-                BindleError::BindleParcelNotFetchedError(model_parcel.label.name.to_string())
-            })?;
+        let model_data_blob: Vec<u8> = loader.get_parcel(bindle_url, model_parcel).await?;
         log::info!(
             "successfully downloaded model '{}' of size {}",
             model_parcel.label.name,
             model_data_blob.len()
         );
 
-        let metadata_blob: Vec<u8> = bindle_client
-            .get_parcel(bindle_url, &metadata_parcel.label.sha256)
-            .await
-            .map_err(|_| {
-                log::error!("Bindle Parcel 'metadata' could not be fetched!");
-                BindleError::BindleParcelNotFetchedError(metadata_parcel.label.name.to_string())
+        let metadata_blob: Option<Vec<u8>> = match metadata_parcel {
+            Some(metadata_parcel) => {
+                let blob = loader.get_parcel(bindle_url, metadata_parcel).await?;
+                log::info!(
+                    "successfully downloaded metadata '{}' of size {}",
+                    metadata_parcel.label.name,
+                    blob.len()
+                );
+                Some(blob)
+            }
+            None => {
+                log::warn!(
+                    "No Bindle Parcel of group 'metadata' for '{}', inferring it from the model",
+                    bindle_url
+                );
+                None
+            }
+        };
+
+        // missing or partial metadata is completed by what the model declares
+        let mut metadata: ModelMetadata =
+            complete_metadata(bindle_url, metadata_blob.as_deref(), &model_data_blob).map_err(
+                |error| {
+                    log::error!("BindleParsingMetadataError: '{}'", error);
+                    BindleError::BindleParsingMetadataError(format!("{}", error))
+                },
+            )?;
+
+        if let Some(labels_parcel) = labels_parcel {
+            let blob = loader.get_parcel(bindle_url, labels_parcel).await?;
+
+            let labels = String::from_utf8(blob).map_err(|error| {
+                log::error!("BindleParsingLabelsError: '{}'", error);
+                BindleError::BindleParsingLabelsError(format!("{}", error))
             })?;
+            metadata.labels = Some(labels.lines().map(str::to_string).collect());
+            log::info!(
+                "successfully downloaded {} labels '{}'",
+                labels.lines().count(),
+                labels_parcel.label.name
+            );
+        }
+
+        metadata.signer = signer;
+
+        // a variant's annotations take precedence over metadata shared by all variants
+        if let Some(encoding) = variant.encoding {
+            metadata.graph_encoding = encoding;
+        }
+        if let Some(target) = variant.target {
+            metadata.execution_target = target;
+        }
+
+        Ok((metadata, model_data_blob))
+    }
+
+    /// get warm-up samples, i.e. raw input tensors being member of the optional group 'warmup'
+    pub async fn get_warmup_samples(
+        loader: &dyn ModelLoader,
+        bindle_url: &str,
+    ) -> BindleResult<Vec<Vec<u8>>> {
+        let (parcels, _) = loader.get_parcels(bindle_url).await?;
+        let mut samples: Vec<Vec<u8>> = Vec::new();
+
+        for parcel in BindleLoader::get_members_of(&parcels, "warmup") {
+            let blob: Vec<u8> = loader.get_parcel(bindle_url, parcel).await?;
+            log::info!(
+                "successfully downloaded warm-up sample '{}' of size {}",
+                parcel.label.name,
+                blob.len()
+            );
+            samples.push(blob);
+        }
+
+        Ok(samples)
+    }
+
+    /// get the reference input profile, i.e. the first parcel of the optional group 'profile'
+    pub async fn get_profile(
+        loader: &dyn ModelLoader,
+        bindle_url: &str,
+    ) -> BindleResult<Option<InputProfile>> {
+        let (parcels, _) = loader.get_parcels(bindle_url).await?;
+        let profile_parcel = match BindleLoader::get_members_of(&parcels, "profile").first() {
+            Some(&p) => p,
+            None => return Ok(None),
+        };
+
+        let profile_blob: Vec<u8> = loader.get_parcel(bindle_url, profile_parcel).await?;
+
+        InputProfile::from_json(&profile_blob)
+            .map(Some)
+            .map_err(|error| {
+                log::error!("BindleParsingProfileError: '{}'", error);
+                BindleError::BindleParsingProfileError(format!("{}", error))
+            })
+    }
+
+    /// get the CPU variant of an Edge TPU model, i.e. the first parcel of the optional group 'model-cpu',
+    /// else the first TfLite variant of group 'model' annotated by target 'cpu'
+    pub async fn get_cpu_variant(
+        loader: &dyn ModelLoader,
+        bindle_url: &str,
+    ) -> BindleResult<Option<Vec<u8>>> {
+        let (parcels, _) = loader.get_parcels(bindle_url).await?;
+        let variant_parcel = match BindleLoader::find_cpu_variant(&parcels) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let variant_blob: Vec<u8> = loader.get_parcel(bindle_url, variant_parcel).await?;
         log::info!(
-            "successfully downloaded metadata '{}' of size {}",
-            metadata_parcel.label.name,
-            metadata_blob.len()
+            "successfully downloaded CPU variant '{}' of size {}",
+            variant_parcel.label.name,
+            variant_blob.len()
         );
 
-        // storing metadata makes sense when model data is done
-        let metadata: ModelMetadata =
-            ModelMetadata::from_json(&metadata_blob).map_err(|error| {
-                log::error!("BindleParsingMetadataError: '{}'", error);
-                BindleError::BindleParsingMetadataError(format!("{}", error))
-            })?;
+        Ok(Some(variant_blob))
+    }
 
-        Ok((metadata, model_data_blob))
+    fn find_cpu_variant(parcels: &[bindle::Parcel]) -> Option<&bindle::Parcel> {
+        let tflite_on_cpu = |parcel: &&bindle::Parcel| {
+            ModelVariant::of(parcel).is_ok_and(|variant| {
+                variant.encoding == Some(GraphEncoding::TfLite)
+                    && variant.target == Some(ExecutionTarget::Cpu)
+            })
+        };
+
+        BindleLoader::get_members_of(parcels, CPU_VARIANT_GROUP)
+            .first()
+            .copied()
+            .or_else(|| {
+                BindleLoader::get_members_of(parcels, MODEL_GROUP)
+                    .into_iter()
+                    .find(tflite_on_cpu)
+            })
     }
 
-    /// get first member of
-    fn get_first_member_of<'a>(
+    /// get the member of a group describing the given model parcel, i.e. annotated by
+    /// the parcel's name, else the first member without annotation shared by all models
+    fn get_member_describing<'a>(
         parcels: &'a [bindle::Parcel],
-        group: &'a str,
-    ) -> BindleResult<&'a bindle::Parcel> {
-        let members = parcels
+        group: &str,
+        model_parcel: &bindle::Parcel,
+    ) -> Option<&'a bindle::Parcel> {
+        let members = BindleLoader::get_members_of(parcels, group);
+        let described_model = |parcel: &bindle::Parcel| {
+            parcel
+                .label
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(MODEL_ANNOTATION).cloned())
+        };
+
+        members
+            .iter()
+            .find(|p| described_model(p).as_ref() == Some(&model_parcel.label.name))
+            .or_else(|| members.iter().find(|p| described_model(p).is_none()))
+            .copied()
+    }
+
+    /// get all members of
+    pub(crate) fn get_members_of<'a>(
+        parcels: &'a [bindle::Parcel],
+        group: &str,
+    ) -> Vec<&'a bindle::Parcel> {
+        parcels
             .iter()
             .filter(|parcel| {
                 parcel.conditions.is_some()
@@ -148,16 +354,7 @@ impl BindleLoader {
                     .iter()
                     .any(|mbs| *mbs == group)
             })
-            .collect::<Vec<&bindle::Parcel>>();
-
-        if members.is_empty() {
-            return Err(BindleError::BindleNoParcelOfGroupModelError);
-        };
-
-        members
-            .into_iter()
-            .next()
-            .ok_or(BindleError::BindleNoParcelOfGroupModelError)
+            .collect::<Vec<&bindle::Parcel>>()
     }
 }
 
@@ -184,9 +381,114 @@ pub enum BindleError {
     #[error("The invoice must have >0 parcels being member of group 'model'")]
     BindleNoParcelOfGroupModelError,
 
+    #[error("No model variant of {0} is supported by this build and configuration")]
+    BindleNoMatchingVariantError(String),
+
     #[error("The invoice must have >0 parcels being member of group 'metadata'")]
     BindleNoParcelOfGroupMetadataError,
 
     #[error("Error parsing metadata {0}")]
     BindleParsingMetadataError(String),
+
+    #[error("invoice {0} is not signed, set 'signatures.allow_unsigned' to load it anyway")]
+    BindleUnsignedInvoiceError(String),
+
+    #[error("invoice {0} failed signature verification: {1}")]
+    BindleSignatureError(String, String),
+
+    #[error("keyring {0}")]
+    BindleKeyringError(String),
+
+    #[error("parcel {0} does not match the sha256 of its invoice")]
+    BindleParcelHashMismatchError(String),
+
+    #[error("parcel {0} has an invalid sha256 in its invoice")]
+    BindleParcelInvalidHashError(String),
+
+    #[error("{0} could not be downloaded")]
+    BindleDownloadError(String),
+
+    #[error("download cache {0}")]
+    BindleCacheError(String),
+
+    #[error(transparent)]
+    Oci(#[from] crate::OciError),
+
+    #[error("Error parsing labels {0}")]
+    BindleParsingLabelsError(String),
+
+    #[error("Error parsing profile {0}")]
+    BindleParsingProfileError(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        build_invoice, InvoiceSpec, ParcelFile, ENCODING_ANNOTATION, MODEL_GROUP, TARGET_ANNOTATION,
+    };
+
+    #[test]
+    fn labels_describing_a_variant_are_preferred() {
+        let spec = InvoiceSpec {
+            name: "mobilenet".to_string(),
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        let parcels = vec![
+            ParcelFile::new("m.onnx", "application/octet-stream", b"f", &[MODEL_GROUP]),
+            ParcelFile::new("m.tflite", "application/octet-stream", b"u", &[MODEL_GROUP]),
+            ParcelFile::new("onnx.txt", "text/plain", b"tench", &[LABELS_GROUP]),
+            ParcelFile::new("tflite.txt", "text/plain", b"background", &[LABELS_GROUP])
+                .with_annotation(MODEL_ANNOTATION, "m.tflite"),
+        ];
+        let invoice = build_invoice(&spec, &parcels);
+        let parcels = invoice.parcel.unwrap();
+
+        let labels_of = |model: usize| {
+            BindleLoader::get_member_describing(&parcels, LABELS_GROUP, &parcels[model])
+                .map(|p| p.label.name.clone())
+        };
+
+        assert_eq!(labels_of(0), Some("onnx.txt".to_string()));
+        assert_eq!(labels_of(1), Some("tflite.txt".to_string()));
+    }
+
+    #[test]
+    fn cpu_variant_falls_back_to_a_model_variant() {
+        let spec = InvoiceSpec {
+            name: "mobilenet".to_string(),
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        let variant = |name: &str, encoding: &str, target: &str| {
+            ParcelFile::new(
+                name,
+                "application/octet-stream",
+                name.as_bytes(),
+                &[MODEL_GROUP],
+            )
+            .with_annotation(ENCODING_ANNOTATION, encoding)
+            .with_annotation(TARGET_ANNOTATION, target)
+        };
+        let mut parcels = vec![
+            variant("m_edgetpu.tflite", "tf_lite", "tpu"),
+            variant("m.onnx", "onnx", "cpu"),
+            variant("m.tflite", "tf_lite", "cpu"),
+        ];
+        let cpu_variant_of = |parcels: &[ParcelFile]| {
+            let invoice = build_invoice(&spec, parcels);
+            BindleLoader::find_cpu_variant(&invoice.parcel.unwrap()).map(|p| p.label.name.clone())
+        };
+
+        assert_eq!(cpu_variant_of(&parcels), Some("m.tflite".to_string()));
+
+        parcels.push(ParcelFile::new(
+            "cpu.tflite",
+            "application/octet-stream",
+            b"c",
+            &[CPU_VARIANT_GROUP],
+        ));
+        assert_eq!(cpu_variant_of(&parcels), Some("cpu.tflite".to_string()));
+    }
 }
@@ -0,0 +1,228 @@
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use wasmcloud_interface_mlinference::{InferenceOutput, Tensor};
+
+/// Settings of the per-model inference result cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheSettings {
+    /// maximum number of cached results per model, 0 disables caching
+    #[serde(default)]
+    pub capacity: usize,
+
+    /// time in seconds after which a cached result expires (never if not set)
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+impl CacheSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+}
+
+/// Digest of everything determining an inference result
+pub type CacheKey = [u8; 32];
+
+/// LRU cache of inference results of a single model
+pub struct InferenceCache {
+    settings: CacheSettings,
+    entries: Mutex<LruCache<CacheKey, (Instant, InferenceOutput)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl InferenceCache {
+    pub fn new(settings: CacheSettings) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(settings.capacity)),
+            settings,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// hash of model id, output index and input tensor, preceded by the lengths
+    /// of their variable parts such that no two inputs hash the same bytes
+    pub fn key(model_id: &str, index: u32, tensor: &Tensor) -> CacheKey {
+        let value_types = format!("{:?}", tensor.value_types);
+        let mut hasher = Sha256::new();
+        for len in [
+            model_id.len(),
+            value_types.len(),
+            tensor.dimensions.len(),
+            tensor.data.len(),
+        ] {
+            hasher.update((len as u64).to_le_bytes());
+        }
+        hasher.update(model_id.as_bytes());
+        hasher.update(index.to_le_bytes());
+        hasher.update(value_types.as_bytes());
+        for d in tensor.dimensions.iter() {
+            hasher.update(d.to_le_bytes());
+        }
+        hasher.update(tensor.flags.to_le_bytes());
+        hasher.update(&tensor.data);
+        hasher.finalize().into()
+    }
+
+    /// cached result, if present and not expired
+    pub fn get(&self, key: &CacheKey) -> Option<InferenceOutput> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let expired = match entries.get(key) {
+            Some((inserted, output)) => match self.settings.ttl_secs {
+                Some(ttl) if inserted.elapsed() > Duration::from_secs(ttl) => true,
+                _ => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(output.clone());
+                }
+            },
+            None => false,
+        };
+
+        if expired {
+            entries.pop(key);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    pub fn put(&self, key: CacheKey, output: InferenceOutput) {
+        self.entries
+            .lock()
+            .unwrap()
+            .put(key, (Instant::now(), output));
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Debug for InferenceCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InferenceCache")
+            .field("settings", &self.settings)
+            .field("hits", &self.hits())
+            .field("misses", &self.misses())
+            .finish()
+    }
+}
+
+impl PartialEq for InferenceCache {
+    fn eq(&self, other: &Self) -> bool {
+        self.settings == other.settings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasmcloud_interface_mlinference::{Status, ValueType};
+
+    fn tensor(data: Vec<u8>) -> Tensor {
+        Tensor {
+            value_types: vec![ValueType::ValueU8],
+            dimensions: vec![1, data.len() as u32],
+            flags: 0,
+            data,
+        }
+    }
+
+    fn output(data: Vec<u8>) -> InferenceOutput {
+        InferenceOutput {
+            result: Status::Success,
+            tensor: tensor(data),
+        }
+    }
+
+    #[test]
+    fn key_covers_model_and_input() {
+        let t = tensor(vec![1, 2, 3]);
+        assert_eq!(
+            InferenceCache::key("a", 0, &t),
+            InferenceCache::key("a", 0, &t)
+        );
+        assert_ne!(
+            InferenceCache::key("a", 0, &t),
+            InferenceCache::key("b", 0, &t)
+        );
+        assert_ne!(
+            InferenceCache::key("a", 0, &t),
+            InferenceCache::key("a", 0, &tensor(vec![1, 2, 4]))
+        );
+    }
+
+    #[test]
+    fn key_separates_dimensions_from_data() {
+        let t = Tensor {
+            value_types: vec![ValueType::ValueU8],
+            dimensions: vec![2],
+            flags: 0,
+            data: vec![7],
+        };
+        // the same bytes, shifted from the dimensions over the flags into the data
+        let shifted = Tensor {
+            value_types: vec![ValueType::ValueU8],
+            dimensions: vec![],
+            flags: 2,
+            data: vec![0, 0, 0, 0, 7],
+        };
+
+        assert_ne!(
+            InferenceCache::key("a", 0, &t),
+            InferenceCache::key("a", 0, &shifted)
+        );
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = InferenceCache::new(CacheSettings {
+            capacity: 2,
+            ttl_secs: None,
+        });
+        let (a, b, c) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+
+        cache.put(a, output(vec![1]));
+        cache.put(b, output(vec![2]));
+        assert!(cache.get(&a).is_some());
+        cache.put(c, output(vec![3]));
+
+        assert!(cache.get(&b).is_none());
+        assert_eq!(cache.get(&c).unwrap().tensor.data, vec![3]);
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let cache = InferenceCache::new(CacheSettings {
+            capacity: 2,
+            ttl_secs: Some(0),
+        });
+        cache.put([0u8; 32], output(vec![0]));
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(cache.get(&[0u8; 32]).is_none());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,888 @@
+//! Resilient downloads from the bindle server or an OCI registry
+//!
+//! Failed requests are retried with exponential backoff, and each request
+//! is bounded by a timeout, both for the response and for each chunk of its
+//! body. Parcels are streamed rather than buffered by the bindle client.
+//! If a cache directory is configured, they are streamed to a partial file
+//! named by their sha256 and renamed once complete. An interrupted download
+//! resumes from the bytes already received by an HTTP range request, within
+//! a link by its retries and across links by the partial file. A parcel is
+//! downloaded by one link at a time, others wait for it and find it in the
+//! cache. Completed parcels are verified against their sha256 and served
+//! from the cache.
+use crate::{
+    bindle_loader::{BindleError, BindleResult},
+    verify_parcel,
+};
+use bindle::client::{tokens::NoToken, Client, ClientError};
+use futures_util::StreamExt;
+use reqwest::{
+    header::{ACCEPT, RANGE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::io::AsyncWriteExt;
+
+/// Settings of downloads from the bindle server or an OCI registry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadSettings {
+    /// retries of a failed request, 0 to fail on the first error
+    #[serde(default = "DownloadSettings::default_retries")]
+    pub retries: u32,
+
+    /// delay before the first retry in milliseconds, doubled for each further retry
+    #[serde(default = "DownloadSettings::default_backoff_ms")]
+    pub backoff_ms: u64,
+
+    /// upper bound of the delay between retries in milliseconds
+    #[serde(default = "DownloadSettings::default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+
+    /// time in milliseconds to wait for a response or for the next chunk of its body
+    #[serde(default = "DownloadSettings::default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// directory parcels are streamed to and cached in by their sha256 (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// size in bytes from which the progress of a download is logged
+    #[serde(default = "DownloadSettings::default_progress_bytes")]
+    pub progress_bytes: u64,
+}
+
+impl DownloadSettings {
+    fn default_retries() -> u32 {
+        3
+    }
+
+    fn default_backoff_ms() -> u64 {
+        500
+    }
+
+    fn default_max_backoff_ms() -> u64 {
+        10_000
+    }
+
+    fn default_timeout_ms() -> u64 {
+        30_000
+    }
+
+    fn default_progress_bytes() -> u64 {
+        16 * 1024 * 1024
+    }
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            retries: DownloadSettings::default_retries(),
+            backoff_ms: DownloadSettings::default_backoff_ms(),
+            max_backoff_ms: DownloadSettings::default_max_backoff_ms(),
+            timeout_ms: DownloadSettings::default_timeout_ms(),
+            cache_dir: None,
+            progress_bytes: DownloadSettings::default_progress_bytes(),
+        }
+    }
+}
+
+/// Failure of a single attempt, worth a retry or not
+enum Attempt {
+    Retry(String),
+    Fatal(BindleError),
+}
+
+/// Delays between retries, doubled up to a maximum
+struct Backoff {
+    delay: Duration,
+    max_delay: Duration,
+    retries: u32,
+    retried: u32,
+}
+
+impl Backoff {
+    fn new(settings: &DownloadSettings) -> Self {
+        Backoff {
+            delay: Duration::from_millis(settings.backoff_ms),
+            max_delay: Duration::from_millis(settings.max_backoff_ms),
+            retries: settings.retries,
+            retried: 0,
+        }
+    }
+
+    /// waits before the next retry of 'what', false if all retries are used up
+    async fn wait(&mut self, what: &str, reason: &str) -> bool {
+        if self.retried >= self.retries {
+            log::error!("{} failed after {} retries: {}", what, self.retried, reason);
+            return false;
+        }
+
+        self.retried += 1;
+        log::warn!(
+            "{} failed: {}, retry {} of {} in {:?}",
+            what,
+            reason,
+            self.retried,
+            self.retries,
+            self.delay
+        );
+        tokio::time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(self.max_delay);
+
+        true
+    }
+}
+
+/// Locks of the partial files in any cache directory, shared by the downloaders of all links
+static PARTS: Mutex<BTreeMap<PathBuf, Arc<tokio::sync::Mutex<()>>>> = Mutex::new(BTreeMap::new());
+
+/// whether 'sha256' is a hex digest as named by the cache, 64 lowercase hex digits
+fn is_sha256(sha256: &str) -> bool {
+    sha256.len() == 64
+        && sha256
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// partial file of a parcel in 'cache_dir'
+fn part_path(cache_dir: &Path, sha256: &str) -> PathBuf {
+    cache_dir.join(format!("{}.part", sha256))
+}
+
+/// waits until no other download writes the partial file 'part'
+async fn lock_part(part: &Path) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = PARTS
+        .lock()
+        .unwrap()
+        .entry(part.to_path_buf())
+        .or_default()
+        .clone();
+
+    lock.lock_owned().await
+}
+
+/// Destination of a parcel's bytes, a partial file in the cache directory or memory
+enum Sink {
+    File {
+        file: tokio::fs::File,
+        part: PathBuf,
+        path: PathBuf,
+        len: u64,
+    },
+    Memory(Vec<u8>),
+}
+
+impl Sink {
+    /// opens the partial file of a parcel in 'cache_dir' to append to, else memory
+    async fn open(cache_dir: Option<&Path>, sha256: &str) -> std::io::Result<Self> {
+        let dir = match cache_dir {
+            Some(dir) => dir,
+            None => return Ok(Sink::Memory(Vec::new())),
+        };
+
+        tokio::fs::create_dir_all(dir).await?;
+        let part = part_path(dir, sha256);
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part)
+            .await?;
+        let len = file.metadata().await?.len();
+
+        Ok(Sink::File {
+            file,
+            part,
+            path: dir.join(sha256),
+            len,
+        })
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            Sink::File { len, .. } => *len,
+            Sink::Memory(data) => data.len() as u64,
+        }
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::File { file, len, .. } => {
+                file.write_all(chunk).await?;
+                *len += chunk.len() as u64;
+            }
+            Sink::Memory(data) => data.extend_from_slice(chunk),
+        }
+
+        Ok(())
+    }
+
+    /// discards the bytes received so far
+    async fn truncate(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::File { file, len, .. } => {
+                file.set_len(0).await?;
+                *len = 0;
+            }
+            Sink::Memory(data) => data.clear(),
+        }
+
+        Ok(())
+    }
+
+    /// the complete parcel, verified against its sha256 and moved into the cache
+    async fn finish(self, parcel: &bindle::Parcel) -> BindleResult<Vec<u8>> {
+        match self {
+            Sink::File {
+                mut file,
+                part,
+                path,
+                ..
+            } => {
+                let data = async {
+                    file.flush().await?;
+                    drop(file);
+                    tokio::fs::read(&part).await
+                }
+                .await
+                .map_err(|e| cache_error(&part, e))?;
+
+                if let Err(error) = verify_parcel(parcel, &data) {
+                    // a corrupt partial file must not be resumed
+                    let _ = tokio::fs::remove_file(&part).await;
+                    return Err(error);
+                }
+                tokio::fs::rename(&part, &path)
+                    .await
+                    .map_err(|e| cache_error(&path, e))?;
+
+                Ok(data)
+            }
+            Sink::Memory(data) => {
+                verify_parcel(parcel, &data)?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+fn cache_error(path: &Path, error: std::io::Error) -> BindleError {
+    log::error!("download cache '{}': {}", path.display(), error);
+    BindleError::BindleCacheError(format!("{}: {}", path.display(), error))
+}
+
+/// Downloads invoices and parcels from a bindle server, blobs and documents from any url
+pub struct Downloader {
+    base_url: String,
+    bindle_client: Client<NoToken>,
+    http_client: reqwest::Client,
+    settings: DownloadSettings,
+}
+
+impl Downloader {
+    pub fn new(base_url: &str, settings: DownloadSettings) -> BindleResult<Self> {
+        let bindle_client = Client::new(base_url, NoToken).map_err(|_| {
+            log::error!("Bindle Url invalid!");
+            BindleError::BindleUrlInvalidError
+        })?;
+
+        Ok(Downloader {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            bindle_client,
+            http_client: reqwest::Client::new(),
+            settings,
+        })
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.settings.timeout_ms)
+    }
+
+    /// get the invoice of a bindle, retrying on failure
+    pub async fn get_invoice(&self, bindle_id: &str) -> BindleResult<bindle::Invoice> {
+        let what = format!("download of invoice '{}'", bindle_id);
+        let mut backoff = Backoff::new(&self.settings);
+
+        loop {
+            let reason = match tokio::time::timeout(
+                self.timeout(),
+                self.bindle_client.get_invoice(bindle_id),
+            )
+            .await
+            {
+                Ok(Ok(invoice)) => return Ok(invoice),
+                Ok(Err(ClientError::InvoiceNotFound)) => {
+                    log::error!("Bindle Invoice not found!");
+                    return Err(BindleError::BindleInvoiceNotFoundError(
+                        bindle_id.to_string(),
+                    ));
+                }
+                Ok(Err(error)) => error.to_string(),
+                Err(_) => format!("no response within {:?}", self.timeout()),
+            };
+
+            if !backoff.wait(&what, &reason).await {
+                return Err(BindleError::BindleInvoiceNotFoundError(
+                    bindle_id.to_string(),
+                ));
+            }
+        }
+    }
+
+    /// get a small document such as a manifest of media type 'accept', None if there is none
+    pub async fn get_document(&self, url: &str, accept: &str) -> BindleResult<Option<Vec<u8>>> {
+        let what = format!("download of '{}'", url);
+        let mut backoff = Backoff::new(&self.settings);
+
+        loop {
+            let reason = match self.try_get_document(url, accept).await {
+                Ok(document) => return Ok(document),
+                Err(Attempt::Fatal(error)) => return Err(error),
+                Err(Attempt::Retry(reason)) => reason,
+            };
+
+            if !backoff.wait(&what, &reason).await {
+                return Err(BindleError::BindleDownloadError(url.to_string()));
+            }
+        }
+    }
+
+    async fn try_get_document(&self, url: &str, accept: &str) -> Result<Option<Vec<u8>>, Attempt> {
+        let request = self.http_client.get(url).header(ACCEPT, accept);
+        let response = tokio::time::timeout(self.timeout(), request.send())
+            .await
+            .map_err(|_| Attempt::Retry(format!("no response within {:?}", self.timeout())))?
+            .map_err(|e| Attempt::Retry(e.to_string()))?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(Attempt::Retry(format!("status {}", status)));
+        }
+        if !status.is_success() {
+            log::error!("'{}' could not be fetched: {}", url, status);
+            return Err(Attempt::Fatal(BindleError::BindleDownloadError(
+                url.to_string(),
+            )));
+        }
+
+        tokio::time::timeout(self.timeout(), response.bytes())
+            .await
+            .map_err(|_| Attempt::Retry(format!("stalled for {:?}", self.timeout())))?
+            .map(|body| Some(body.to_vec()))
+            .map_err(|e| Attempt::Retry(e.to_string()))
+    }
+
+    /// get a parcel of a bindle, resuming an interrupted download and verifying its sha256
+    pub async fn get_parcel(
+        &self,
+        bindle_id: &str,
+        parcel: &bindle::Parcel,
+    ) -> BindleResult<Vec<u8>> {
+        let url = format!("{}/_i/{}@{}", self.base_url, bindle_id, parcel.label.sha256);
+
+        self.get_blob(&url, parcel).await
+    }
+
+    /// get the content of a parcel from any 'url', e.g. a blob of an OCI registry
+    pub async fn get_blob(&self, url: &str, parcel: &bindle::Parcel) -> BindleResult<Vec<u8>> {
+        let name = parcel.label.name.as_str();
+        let sha256 = parcel.label.sha256.as_str();
+
+        // the sha256 names files of the cache directory, it must not name any other path
+        if !is_sha256(sha256) {
+            log::error!("parcel '{}' has an invalid sha256 '{}'", name, sha256);
+            return Err(BindleError::BindleParcelInvalidHashError(name.to_string()));
+        }
+
+        // held until the parcel is complete, concurrent downloads then find it in the cache
+        let _part = match &self.settings.cache_dir {
+            Some(dir) => Some(lock_part(&part_path(dir, sha256)).await),
+            None => None,
+        };
+
+        if let Some(dir) = &self.settings.cache_dir {
+            let path = dir.join(sha256);
+            if let Ok(data) = tokio::fs::read(&path).await {
+                if verify_parcel(parcel, &data).is_ok() {
+                    log::info!("parcel '{}' found in '{}'", name, dir.display());
+                    return Ok(data);
+                }
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+
+        let cache_dir = self.settings.cache_dir.as_deref();
+        let mut sink = Sink::open(cache_dir, sha256)
+            .await
+            .map_err(|e| cache_error(cache_dir.unwrap_or_else(|| Path::new("")), e))?;
+        let what = format!("download of parcel '{}'", name);
+        let mut backoff = Backoff::new(&self.settings);
+
+        loop {
+            match self.try_get_blob(url, parcel, &mut sink).await {
+                Ok(()) => break,
+                Err(Attempt::Fatal(error)) => return Err(error),
+                Err(Attempt::Retry(reason)) => {
+                    if !backoff.wait(&what, &reason).await {
+                        return Err(BindleError::BindleParcelNotFetchedError(name.to_string()));
+                    }
+                }
+            }
+        }
+
+        sink.finish(parcel).await
+    }
+
+    /// a single request for the rest of a parcel, appending to 'sink'
+    async fn try_get_blob(
+        &self,
+        url: &str,
+        parcel: &bindle::Parcel,
+        sink: &mut Sink,
+    ) -> Result<(), Attempt> {
+        let name = parcel.label.name.as_str();
+        let size = parcel.label.size;
+        let io_error = |e| Attempt::Fatal(cache_error(Path::new(name), e));
+
+        // a complete partial file is verified as it is, one exceeding the parcel is stale
+        if sink.len() == size {
+            return Ok(());
+        }
+        if sink.len() > size {
+            log::warn!("restarting parcel '{}', its partial file is too long", name);
+            sink.truncate().await.map_err(io_error)?;
+        }
+
+        let mut offset = sink.len();
+        let mut response = self.send_from(url, offset).await?;
+        if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            log::warn!(
+                "restarting parcel '{}', the server refuses to resume at {} bytes",
+                name,
+                offset
+            );
+            sink.truncate().await.map_err(io_error)?;
+            offset = 0;
+            response = self.send_from(url, offset).await?;
+        }
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            log::error!("Bindle Parcel '{}' not found!", name);
+            return Err(Attempt::Fatal(BindleError::BindleParcelNotFoundError(
+                name.to_string(),
+            )));
+        }
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(Attempt::Retry(format!("status {}", status)));
+        }
+        if !status.is_success() {
+            log::error!("Bindle Parcel '{}' could not be fetched: {}", name, status);
+            return Err(Attempt::Fatal(BindleError::BindleParcelNotFetchedError(
+                name.to_string(),
+            )));
+        }
+
+        if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+            log::info!("resuming parcel '{}' at {} of {} bytes", name, offset, size);
+        } else if offset > 0 {
+            log::info!("restarting parcel '{}', the server ignores ranges", name);
+            sink.truncate().await.map_err(io_error)?;
+        }
+
+        let mut stream = response.bytes_stream();
+        let log_progress = size >= self.settings.progress_bytes;
+        let mut next_percent = (sink.len() * 10 / size.max(1) + 1) * 10;
+
+        loop {
+            let chunk = match tokio::time::timeout(self.timeout(), stream.next()).await {
+                Ok(Some(Ok(chunk))) => chunk,
+                Ok(Some(Err(error))) => return Err(Attempt::Retry(error.to_string())),
+                Ok(None) => break,
+                Err(_) => {
+                    return Err(Attempt::Retry(format!(
+                        "stalled for {:?} after {} of {} bytes",
+                        self.timeout(),
+                        sink.len(),
+                        size
+                    )))
+                }
+            };
+            sink.write(&chunk).await.map_err(io_error)?;
+            if sink.len() > size {
+                return Err(Attempt::Retry(format!(
+                    "received more than the {} bytes of the parcel",
+                    size
+                )));
+            }
+
+            if log_progress && sink.len() * 100 >= next_percent * size {
+                log::info!(
+                    "downloading parcel '{}': {}% of {} bytes",
+                    name,
+                    sink.len() * 100 / size,
+                    size
+                );
+                next_percent = sink.len() * 10 / size * 10 + 10;
+            }
+        }
+
+        if sink.len() < size {
+            return Err(Attempt::Retry(format!(
+                "connection closed after {} of {} bytes",
+                sink.len(),
+                size
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// requests the bytes of a parcel from 'offset' on
+    async fn send_from(&self, url: &str, offset: u64) -> Result<reqwest::Response, Attempt> {
+        let mut request = self.http_client.get(url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        tokio::time::timeout(self.timeout(), request.send())
+            .await
+            .map_err(|_| Attempt::Retry(format!("no response within {:?}", self.timeout())))?
+            .map_err(|e| Attempt::Retry(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::invoice::single_model_invoice;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    /// answer of the stand-in bindle server to a request
+    #[derive(Clone, Copy)]
+    enum Reply {
+        /// status 503
+        Unavailable,
+        /// headers, then nothing
+        Stall,
+        /// half of the requested bytes, then the connection is closed
+        Truncate,
+        /// the requested bytes, honoring a range
+        Full,
+        /// status 416, as if the range started beyond the parcel
+        Unsatisfiable,
+    }
+
+    fn parcel_of(data: &[u8]) -> bindle::Parcel {
+        single_model_invoice(data).parcel.unwrap().remove(0)
+    }
+
+    fn settings(cache_dir: Option<PathBuf>) -> DownloadSettings {
+        DownloadSettings {
+            backoff_ms: 10,
+            max_backoff_ms: 40,
+            timeout_ms: 200,
+            cache_dir,
+            ..Default::default()
+        }
+    }
+
+    async fn read_head(socket: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") && socket.read(&mut byte).await.unwrap() == 1 {
+            head.push(byte[0]);
+        }
+
+        String::from_utf8_lossy(&head).to_lowercase()
+    }
+
+    async fn answer(
+        mut socket: TcpStream,
+        data: Vec<u8>,
+        reply: Reply,
+        received: Arc<Mutex<Vec<String>>>,
+    ) {
+        let head = read_head(&mut socket).await;
+        let offset: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("range: bytes="))
+            .and_then(|range| range.trim_end_matches('-').parse().ok())
+            .unwrap_or(0);
+        received.lock().unwrap().push(head);
+
+        let body = &data[offset.min(data.len())..];
+        let status = if offset > 0 {
+            "206 Partial Content"
+        } else {
+            "200 OK"
+        };
+        let header = format!(
+            "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            status,
+            body.len()
+        );
+
+        match reply {
+            Reply::Unavailable => {
+                let _ = socket
+                    .write_all(
+                        b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+            Reply::Stall => {
+                let _ = socket.write_all(header.as_bytes()).await;
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            Reply::Truncate => {
+                let _ = socket.write_all(header.as_bytes()).await;
+                let _ = socket.write_all(&body[..body.len() / 2]).await;
+            }
+            Reply::Full => {
+                let _ = socket.write_all(header.as_bytes()).await;
+                let _ = socket.write_all(body).await;
+            }
+            Reply::Unsatisfiable => {
+                let _ = socket
+                    .write_all(
+                        b"HTTP/1.1 416 Range Not Satisfiable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// serves 'data' on any path, answering the n-th request by the n-th reply and later
+    /// ones in full; returns the base url and the heads of the requests received
+    async fn serve(data: Vec<u8>, replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let received = heads.clone();
+
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (socket, _) = listener.accept().await.unwrap();
+                let reply = replies.get(n).copied().unwrap_or(Reply::Full);
+                tokio::spawn(answer(socket, data.clone(), reply, received.clone()));
+            }
+        });
+
+        (base_url, heads)
+    }
+
+    fn model_data() -> Vec<u8> {
+        (0..4096u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn unavailable_server_is_retried() {
+        let data = model_data();
+        let (url, heads) = serve(data.clone(), vec![Reply::Unavailable, Reply::Unavailable]).await;
+        let downloader = Downloader::new(&url, settings(None)).unwrap();
+
+        let downloaded = downloader
+            .get_parcel("plus3/0.1.0", &parcel_of(&data))
+            .await;
+
+        assert_eq!(downloaded.unwrap(), data);
+        assert_eq!(heads.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_are_bounded() {
+        let data = model_data();
+        let (url, heads) = serve(data.clone(), vec![Reply::Unavailable; 5]).await;
+        let downloader = Downloader::new(
+            &url,
+            DownloadSettings {
+                retries: 1,
+                ..settings(None)
+            },
+        )
+        .unwrap();
+
+        let downloaded = downloader
+            .get_parcel("plus3/0.1.0", &parcel_of(&data))
+            .await;
+
+        assert!(matches!(
+            downloaded,
+            Err(BindleError::BindleParcelNotFetchedError(..))
+        ));
+        assert_eq!(heads.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stalled_downloads_time_out_and_are_retried() {
+        let data = model_data();
+        let (url, heads) = serve(data.clone(), vec![Reply::Stall]).await;
+        let downloader = Downloader::new(&url, settings(None)).unwrap();
+
+        let downloaded = downloader
+            .get_parcel("plus3/0.1.0", &parcel_of(&data))
+            .await;
+
+        assert_eq!(downloaded.unwrap(), data);
+        assert_eq!(heads.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_resume_into_the_cache() {
+        let data = model_data();
+        let parcel = parcel_of(&data);
+        let cache_dir =
+            std::env::temp_dir().join(format!("mlinference-download-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let (url, heads) = serve(data.clone(), vec![Reply::Truncate]).await;
+        let downloader = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+
+        let downloaded = downloader.get_parcel("plus3/0.1.0", &parcel).await;
+
+        assert_eq!(downloaded.unwrap(), data);
+        assert!(heads.lock().unwrap()[1].contains(&format!("range: bytes={}-", data.len() / 2)));
+        assert_eq!(
+            std::fs::read(cache_dir.join(&parcel.label.sha256)).unwrap(),
+            data
+        );
+
+        // a cached parcel is not downloaded again
+        let cached = downloader.get_parcel("plus3/0.1.0", &parcel).await;
+        assert_eq!(cached.unwrap(), data);
+        assert_eq!(heads.lock().unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mlinference-download-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn refused_ranges_restart_from_zero() {
+        let data = model_data();
+        let parcel = parcel_of(&data);
+        let cache_dir = cache_dir("refused");
+        std::fs::write(part_path(&cache_dir, &parcel.label.sha256), b"stale").unwrap();
+        let (url, heads) = serve(data.clone(), vec![Reply::Unsatisfiable]).await;
+        let downloader = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+
+        let downloaded = downloader.get_parcel("plus3/0.1.0", &parcel).await;
+
+        assert_eq!(downloaded.unwrap(), data);
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[0].contains("range: bytes=5-"));
+        assert!(!heads[1].contains("range:"));
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn too_long_partial_files_are_discarded() {
+        let data = model_data();
+        let parcel = parcel_of(&data);
+        let cache_dir = cache_dir("too-long");
+        let stale = [data.clone(), b"stale".to_vec()].concat();
+        std::fs::write(part_path(&cache_dir, &parcel.label.sha256), stale).unwrap();
+        let (url, heads) = serve(data.clone(), vec![]).await;
+        let downloader = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+
+        let downloaded = downloader.get_parcel("plus3/0.1.0", &parcel).await;
+
+        assert_eq!(downloaded.unwrap(), data);
+        assert!(!heads.lock().unwrap()[0].contains("range:"));
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_hashes_are_refused_before_the_cache_is_used() {
+        let data = model_data();
+        let mut parcel = parcel_of(&data);
+        let cache_dir = cache_dir("invalid-hash");
+        let (url, heads) = serve(data.clone(), vec![]).await;
+        let downloader = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+
+        for sha256 in [
+            "../escaped".to_string(),
+            parcel.label.sha256.to_uppercase(),
+            format!("{}0", parcel.label.sha256),
+        ] {
+            parcel.label.sha256 = sha256;
+            assert!(matches!(
+                downloader.get_parcel("plus3/0.1.0", &parcel).await,
+                Err(BindleError::BindleParcelInvalidHashError(..))
+            ));
+        }
+        assert!(heads.lock().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_are_retried() {
+        let data = model_data();
+        let mut parcel = parcel_of(&data);
+        let (url, heads) = serve(data.clone(), vec![]).await;
+        let downloader = Downloader::new(
+            &url,
+            DownloadSettings {
+                retries: 1,
+                ..settings(None)
+            },
+        )
+        .unwrap();
+        parcel.label.size = data.len() as u64 / 2;
+
+        let downloaded = downloader.get_parcel("plus3/0.1.0", &parcel).await;
+
+        assert!(matches!(
+            downloaded,
+            Err(BindleError::BindleParcelNotFetchedError(..))
+        ));
+        assert_eq!(heads.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_share_the_partial_file() {
+        let data = model_data();
+        let parcel = parcel_of(&data);
+        let cache_dir = cache_dir("concurrent");
+        let (url, heads) = serve(data.clone(), vec![]).await;
+        let first = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+        let second = Downloader::new(&url, settings(Some(cache_dir.clone()))).unwrap();
+
+        let (a, b) = tokio::join!(
+            first.get_parcel("plus3/0.1.0", &parcel),
+            second.get_parcel("plus3/0.1.0", &parcel)
+        );
+
+        assert_eq!(a.unwrap(), data);
+        assert_eq!(b.unwrap(), data);
+        assert_eq!(heads.lock().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,539 @@
+use crate::{
+    inference::{
+        ExecutionTarget, Graph, GraphEncoding, GraphExecutionContext, InferenceEngine,
+        InferenceError, InferenceResult,
+    },
+    ModelMetadata,
+};
+use async_trait::async_trait;
+use candle_core::{DType, Device, Module, Tensor as CandleTensor};
+use candle_nn::{Func, Linear, VarBuilder};
+use candle_transformers::models::{bert, resnet};
+use serde::Deserialize;
+use std::{
+    collections::{btree_map::Keys, BTreeMap},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+use wasmcloud_interface_mlinference::{
+    InferenceOutput, Status, Tensor, ValueType, TENSOR_FLAG_ROW_MAJOR,
+};
+
+/// Architectures the weights of a safetensors model may be loaded into.
+/// Selected by 'architecture' in the model's metadata, configured by 'architecture_config'.
+#[derive(Debug, Clone)]
+pub enum CandleArchitecture {
+    /// fully connected layers 'layers.{i}' with ReLU activations in between
+    Mlp(MlpConfig),
+    ResNet18(ResNetConfig),
+    ResNet34(ResNetConfig),
+    ResNet50(ResNetConfig),
+    /// BERT encoder, input are token ids, output is the sequence output
+    Bert(bert::Config),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MlpConfig {
+    /// width of each layer including input and output, e.g. [784, 128, 10]
+    pub layers: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResNetConfig {
+    #[serde(default = "ResNetConfig::default_num_classes")]
+    pub num_classes: usize,
+}
+
+impl ResNetConfig {
+    fn default_num_classes() -> usize {
+        1000
+    }
+}
+
+impl CandleArchitecture {
+    pub fn from_metadata(metadata: &ModelMetadata) -> InferenceResult<Self> {
+        let name = metadata.architecture.as_deref().ok_or_else(|| {
+            log::error!("from_metadata() - safetensors models require an 'architecture'");
+            InferenceError::InvalidArchitecture("none".to_string())
+        })?;
+        let config = metadata
+            .architecture_config
+            .clone()
+            .unwrap_or_else(|| serde_json::Value::Object(Default::default()));
+
+        let parse_error = |e: serde_json::Error| {
+            log::error!("from_metadata() - invalid config of '{}': {}", name, e);
+            InferenceError::InvalidArchitecture(name.to_string())
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "mlp" => Ok(CandleArchitecture::Mlp(
+                serde_json::from_value(config).map_err(parse_error)?,
+            )),
+            "resnet18" => Ok(CandleArchitecture::ResNet18(
+                serde_json::from_value(config).map_err(parse_error)?,
+            )),
+            "resnet34" => Ok(CandleArchitecture::ResNet34(
+                serde_json::from_value(config).map_err(parse_error)?,
+            )),
+            "resnet50" => Ok(CandleArchitecture::ResNet50(
+                serde_json::from_value(config).map_err(parse_error)?,
+            )),
+            "bert" => Ok(CandleArchitecture::Bert(
+                serde_json::from_value(config).map_err(parse_error)?,
+            )),
+            _ => {
+                log::error!("from_metadata() - unknown architecture '{}'", name);
+                Err(InferenceError::InvalidArchitecture(name.to_string()))
+            }
+        }
+    }
+}
+
+pub enum CandleModel {
+    Func(Func<'static>),
+    Mlp(Vec<Linear>),
+    Bert(Box<bert::BertModel>),
+}
+
+impl CandleModel {
+    fn build(
+        architecture: &CandleArchitecture,
+        vb: VarBuilder<'static>,
+    ) -> candle_core::Result<Self> {
+        match architecture {
+            CandleArchitecture::Mlp(config) => {
+                let layers = config
+                    .layers
+                    .windows(2)
+                    .enumerate()
+                    .map(|(i, w)| candle_nn::linear(w[0], w[1], vb.pp(format!("layers.{}", i))))
+                    .collect::<candle_core::Result<Vec<Linear>>>()?;
+                Ok(CandleModel::Mlp(layers))
+            }
+            CandleArchitecture::ResNet18(config) => {
+                Ok(CandleModel::Func(resnet::resnet18(config.num_classes, vb)?))
+            }
+            CandleArchitecture::ResNet34(config) => {
+                Ok(CandleModel::Func(resnet::resnet34(config.num_classes, vb)?))
+            }
+            CandleArchitecture::ResNet50(config) => {
+                Ok(CandleModel::Func(resnet::resnet50(config.num_classes, vb)?))
+            }
+            CandleArchitecture::Bert(config) => Ok(CandleModel::Bert(Box::new(
+                bert::BertModel::load(vb, config)?,
+            ))),
+        }
+    }
+
+    fn forward(&self, input: &CandleTensor) -> candle_core::Result<CandleTensor> {
+        match self {
+            CandleModel::Func(f) => f.forward(input),
+            CandleModel::Mlp(layers) => {
+                let mut x = input.clone();
+                for (i, layer) in layers.iter().enumerate() {
+                    x = layer.forward(&x)?;
+                    if i + 1 < layers.len() {
+                        x = x.relu()?;
+                    }
+                }
+                Ok(x)
+            }
+            CandleModel::Bert(model) => {
+                let input_ids = input.to_dtype(DType::U32)?;
+                let token_type_ids = input_ids.zeros_like()?;
+                model.forward(&input_ids, &token_type_ids, None)
+            }
+        }
+    }
+}
+
+pub struct CandleSession {
+    pub model: CandleModel,
+    pub input_tensors: Option<Vec<CandleTensor>>,
+    pub output_tensors: Option<Vec<CandleTensor>>,
+}
+
+impl CandleSession {
+    pub fn with_model(model: CandleModel) -> Self {
+        Self {
+            model,
+            input_tensors: None,
+            output_tensors: None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CandleEngine {
+    state: Arc<RwLock<ModelState>>,
+}
+
+#[derive(Default)]
+pub struct ModelState {
+    executions: BTreeMap<GraphExecutionContext, CandleSession>,
+    models: BTreeMap<Graph, Vec<u8>>,
+    /// graph of the next loaded model, graphs are not reused once dropped
+    next_graph: Graph,
+    architectures: BTreeMap<Graph, CandleArchitecture>,
+}
+
+impl ModelState {
+    /// Helper function that returns the key that is supposed to be inserted next.
+    pub fn key<K: Into<u32> + From<u32> + Copy, V>(&self, keys: Keys<K, V>) -> K {
+        match keys.last() {
+            Some(&k) => {
+                let last: u32 = k.into();
+                K::from(last + 1)
+            }
+            None => K::from(0),
+        }
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for CandleEngine {
+    /// priority
+    fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+        match (encoding, target) {
+            (GraphEncoding::Safetensors, ExecutionTarget::Cpu) => Some(100),
+            _ => None,
+        }
+    }
+
+    /// load
+    async fn load(&self, model: &[u8]) -> InferenceResult<Graph> {
+        let model_bytes = model.to_vec();
+        let mut state = self.state.write().await;
+        let graph = state.next_graph;
+        state.next_graph += 1;
+
+        log::debug!(
+            "load() - inserting graph: {:#?} with size {:#?}",
+            graph,
+            model_bytes.len()
+        );
+
+        state.models.insert(graph, model_bytes);
+
+        Ok(graph)
+    }
+
+    /// configure
+    async fn configure(&self, graph: Graph, metadata: &ModelMetadata) -> InferenceResult<()> {
+        let architecture = CandleArchitecture::from_metadata(metadata)?;
+
+        log::debug!(
+            "configure() - graph {:#?} uses architecture {:?}",
+            graph,
+            architecture
+        );
+
+        let mut state = self.state.write().await;
+        state.architectures.insert(graph, architecture);
+        Ok(())
+    }
+
+    /// init_execution_context
+    async fn init_execution_context(
+        &self,
+        graph: Graph,
+        target: &ExecutionTarget,
+        encoding: &GraphEncoding,
+    ) -> InferenceResult<GraphExecutionContext> {
+        log::debug!(
+            "init_execution_context() - detected execution target: {:?}, encoding: {:?}",
+            target,
+            encoding
+        );
+
+        if !matches!(target, &ExecutionTarget::Cpu) {
+            log::error!(
+                "This framework does not support execution target '{:?}'",
+                target
+            );
+            return Err(InferenceError::UnsupportedExecutionTarget);
+        }
+
+        if !matches!(encoding, &GraphEncoding::Safetensors) {
+            log::error!(
+                "requested encoding '{:?}' is currently not supported",
+                encoding
+            );
+            return Err(InferenceError::InvalidEncodingError);
+        }
+
+        let mut state = self.state.write().await;
+        let model_bytes = match state.models.get(&graph) {
+            Some(mb) => mb.clone(),
+            None => {
+                log::error!(
+                    "init_execution_context() - cannot find model in state with graph {:#?}",
+                    graph
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+        let architecture = match state.architectures.get(&graph) {
+            Some(a) => a.clone(),
+            None => {
+                log::error!(
+                    "init_execution_context() - graph {:#?} has no architecture configured",
+                    graph
+                );
+                return Err(InferenceError::InvalidArchitecture("none".to_string()));
+            }
+        };
+
+        let vb = VarBuilder::from_buffered_safetensors(model_bytes, DType::F32, &Device::Cpu)?;
+        let model = CandleModel::build(&architecture, vb)?;
+
+        let gec = state.key(state.executions.keys());
+
+        log::debug!(
+            "init_execution_context() - inserting graph execution context: {:#?}",
+            gec
+        );
+
+        state
+            .executions
+            .insert(gec, CandleSession::with_model(model));
+
+        // the raw model is not needed once its session is built
+        state.models.remove(&graph);
+
+        Ok(gec)
+    }
+
+    /// set_input
+    async fn set_input(
+        &self,
+        context: GraphExecutionContext,
+        index: u32,
+        tensor: &Tensor,
+    ) -> InferenceResult<()> {
+        let mut state = self.state.write().await;
+        let execution = match state.executions.get_mut(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "set_input() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let shape: Vec<usize> = tensor.dimensions.iter().map(|&d| d as usize).collect();
+        let input = match tensor.value_types.first() {
+            Some(ValueType::ValueF32) => {
+                let data: Vec<f32> = tensor
+                    .data
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                CandleTensor::from_vec(data, shape, &Device::Cpu)?
+            }
+            Some(ValueType::ValueU8) => {
+                CandleTensor::from_vec(tensor.data.clone(), shape, &Device::Cpu)?
+                    .to_dtype(DType::F32)?
+            }
+            _ => {
+                log::error!(
+                    "set_input() - unsupported value types {:?}",
+                    tensor.value_types
+                );
+                return Err(InferenceError::CorruptInputTensor);
+            }
+        };
+
+        log::debug!(
+            "set_input() - context {:?}, index {}, shape {:?}",
+            context,
+            index,
+            input.dims()
+        );
+
+        execution.input_tensors = Some(vec![input]);
+        Ok(())
+    }
+
+    /// compute()
+    async fn compute(&self, context: GraphExecutionContext) -> InferenceResult<()> {
+        let mut state = self.state.write().await;
+        let execution = match state.executions.get_mut(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "compute() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let input = match execution.input_tensors.as_ref().and_then(|i| i.first()) {
+            Some(i) => i,
+            None => {
+                log::error!("compute() - no input tensor set");
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let output = execution.model.forward(input)?;
+        execution.output_tensors.replace(vec![output]);
+
+        Ok(())
+    }
+
+    /// get_output
+    async fn get_output(
+        &self,
+        context: GraphExecutionContext,
+        index: u32,
+    ) -> InferenceResult<InferenceOutput> {
+        let state = self.state.read().await;
+        let execution = match state.executions.get(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "get_output() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let tensor = match execution
+            .output_tensors
+            .as_ref()
+            .and_then(|o| o.get(index as usize))
+        {
+            Some(t) => t,
+            None => {
+                log::error!(
+                    "get_output() - output_tensors does not contain index {}",
+                    index
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let values: Vec<f32> = tensor.flatten_all()?.to_dtype(DType::F32)?.to_vec1()?;
+
+        Ok(InferenceOutput {
+            result: Status::Success,
+            tensor: Tensor {
+                value_types: vec![ValueType::ValueF32],
+                dimensions: tensor.dims().iter().map(|&d| d as u32).collect(),
+                flags: TENSOR_FLAG_ROW_MAJOR,
+                data: values.iter().flat_map(|f| f.to_le_bytes()).collect(),
+            },
+        })
+    }
+
+    /// remove model state
+    async fn drop_model_state(&self, graph: &Graph, gec: &GraphExecutionContext) {
+        let mut state = self.state.write().await;
+
+        state.models.remove(graph);
+        state.architectures.remove(graph);
+        state.executions.remove(gec);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// weights of an mlp [2, 3, 2] mapping [3, 4] to [7, 0], the ReLU zeroing the third hidden unit
+    fn mlp_safetensors(name: &str) -> Vec<u8> {
+        let tensor =
+            |data: &[f32], shape: &[usize]| CandleTensor::from_slice(data, shape, &Device::Cpu);
+        let tensors: HashMap<String, CandleTensor> = [
+            (
+                "layers.0.weight",
+                tensor(&[1., 0., 0., 1., -1., -1.], &[3, 2]),
+            ),
+            ("layers.0.bias", tensor(&[0., 0., 0.], &[3])),
+            (
+                "layers.1.weight",
+                tensor(&[1., 1., 1., 1., -1., 0.], &[2, 3]),
+            ),
+            ("layers.1.bias", tensor(&[0., 1.], &[2])),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.unwrap()))
+        .collect();
+
+        let path = std::env::temp_dir().join(format!(
+            "candle-{}-{}.safetensors",
+            name,
+            std::process::id()
+        ));
+        candle_core::safetensors::save(&tensors, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        bytes
+    }
+
+    async fn run_mlp(name: &str, input: &Tensor) -> InferenceOutput {
+        let engine = CandleEngine::default();
+        let metadata = ModelMetadata {
+            graph_encoding: GraphEncoding::Safetensors,
+            architecture: Some("mlp".to_string()),
+            architecture_config: Some(serde_json::json!({ "layers": [2, 3, 2] })),
+            ..Default::default()
+        };
+
+        let graph = engine.load(&mlp_safetensors(name)).await.unwrap();
+        engine.configure(graph, &metadata).await.unwrap();
+        let gec = engine
+            .init_execution_context(graph, &ExecutionTarget::Cpu, &GraphEncoding::Safetensors)
+            .await
+            .unwrap();
+        engine.set_input(gec, 0, input).await.unwrap();
+        engine.compute(gec).await.unwrap();
+
+        engine.get_output(gec, 0).await.unwrap()
+    }
+
+    fn f32_values(output: &InferenceOutput) -> Vec<f32> {
+        output
+            .tensor
+            .data
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn mlp_runs() {
+        let input = Tensor {
+            value_types: vec![ValueType::ValueF32],
+            dimensions: vec![1, 2],
+            flags: TENSOR_FLAG_ROW_MAJOR,
+            data: [3f32, 4.].iter().flat_map(|f| f.to_le_bytes()).collect(),
+        };
+        let output = run_mlp("f32", &input).await;
+
+        assert_eq!(output.tensor.dimensions, vec![1, 2]);
+        assert_eq!(f32_values(&output), vec![7., 0.]);
+    }
+
+    #[tokio::test]
+    async fn u8_inputs_are_converted_to_f32() {
+        let input = Tensor {
+            value_types: vec![ValueType::ValueU8],
+            dimensions: vec![1, 2],
+            flags: TENSOR_FLAG_ROW_MAJOR,
+            data: vec![3, 4],
+        };
+        let output = run_mlp("u8", &input).await;
+
+        assert_eq!(output.tensor.dimensions, vec![1, 2]);
+        assert_eq!(f32_values(&output), vec![7., 0.]);
+    }
+}
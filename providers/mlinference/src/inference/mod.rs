@@ -1,12 +1,25 @@
+#[cfg(feature = "candle")]
+mod candle;
+mod quantization;
 #[cfg(feature = "tflite")]
 mod tflite;
 mod tract;
+#[cfg(feature = "tract-tflite")]
+mod tract_tflite;
+
+#[cfg(feature = "candle")]
+pub use self::candle::{CandleArchitecture, CandleEngine};
 
 #[cfg(any(feature = "tflite", feature = "edgetpu"))]
 pub use self::tflite::TfLiteEngine;
+#[cfg(feature = "tract-tflite")]
+pub use self::tract_tflite::TractTfLiteEngine;
+use crate::ModelMetadata;
 use async_trait::async_trait;
+pub(crate) use quantization::quantization;
+pub use quantization::{dequantize, quantize};
 use serde::{Deserialize, Serialize};
-pub use tract::{bytes_to_f32_vec, f32_array_to_bytes, TractEngine, TractSession};
+pub use tract::{bytes_to_f32_vec, f32_array_to_bytes, TractEngine, TractModel, TractSession};
 use wasmcloud_interface_mlinference::{InferenceOutput, Tensor};
 
 /// Graph (model number)
@@ -20,6 +33,8 @@ pub enum GraphEncoding {
     TfLite,
     OpenVino,
     Tensorflow,
+    Safetensors,
+    Nnef,
 }
 
 impl Default for GraphEncoding {
@@ -31,6 +46,37 @@ impl Default for GraphEncoding {
 /// GraphExecutionContext
 pub type GraphExecutionContext = u32;
 
+/// Options of the TfLite interpreter, set per model by its metadata and per link by its settings.
+/// The XNNPACK delegate is not among them, it is compiled in for all models by feature 'xnnpack'.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TfLiteOptions {
+    /// return quantized outputs as raw bytes (ValueU8 or ValueS8) instead of dequantized f32
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_output: Option<bool>,
+
+    /// number of threads of the interpreter, TfLite's default if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<i32>,
+}
+
+impl TfLiteOptions {
+    /// options set here, completed by those of 'fallback'
+    pub fn or(self, fallback: TfLiteOptions) -> TfLiteOptions {
+        TfLiteOptions {
+            raw_output: self.raw_output.or(fallback.raw_output),
+            threads: self.threads.or(fallback.threads),
+        }
+    }
+}
+
+/// A dimension of a model's input, either fixed or named by a symbol, e.g. 'N' for the batch size
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Dimension {
+    Fixed(u32),
+    Symbol(String),
+}
+
 /// ExecutionTarget
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -57,8 +103,16 @@ impl Default for Box<dyn InferenceEngine + Send + Sync> {
 /// InferenceEngine
 #[async_trait]
 pub trait InferenceEngine {
+    /// priority of running models of an encoding on a target, None if unsupported
+    fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32>;
+
     async fn load(&self, model: &[u8]) -> InferenceResult<Graph>;
 
+    /// passes a loaded model's metadata to engines which need more than the graph encoding
+    async fn configure(&self, _graph: Graph, _metadata: &ModelMetadata) -> InferenceResult<()> {
+        Ok(())
+    }
+
     async fn init_execution_context(
         &self,
         graph: Graph,
@@ -130,4 +184,17 @@ pub enum InferenceError {
 
     #[error("Configuration of model's input type and/or shape failed")]
     CorruptInputTypeOrShape(#[from] tract_onnx::tract_core::anyhow::Error),
+
+    #[error("Invalid or unknown model architecture '{0}'")]
+    InvalidArchitecture(String),
+
+    #[error("Invalid input shape: {0}")]
+    InvalidShape(String),
+
+    #[error("No device available for execution target '{0:?}'")]
+    DeviceNotFound(ExecutionTarget),
+
+    #[cfg(feature = "candle")]
+    #[error("Candle error: {0}")]
+    CandleError(#[from] candle_core::Error),
 }
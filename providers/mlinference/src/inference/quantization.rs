@@ -0,0 +1,46 @@
+/// quantization parameters of a tensor, identity for tensors without
+pub(crate) fn quantization(scale: f32, zero_point: i32) -> (f32, i32) {
+    if scale == 0.0 {
+        (1.0, 0)
+    } else {
+        (scale, zero_point)
+    }
+}
+
+/// maps real values into the quantized domain [min, max]
+pub fn quantize(values: &[f32], scale: f32, zero_point: i32, min: i32, max: i32) -> Vec<i32> {
+    values
+        .iter()
+        .map(|&x| ((x / scale).round() as i32 + zero_point).clamp(min, max))
+        .collect()
+}
+
+/// maps quantized values back to real values
+pub fn dequantize(values: &[i32], scale: f32, zero_point: i32) -> Vec<f32> {
+    values
+        .iter()
+        .map(|&q| scale * ((q - zero_point) as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn int8_round_trip() {
+        let values = [-1.0, 0.0, 0.5, 1.0];
+        let quantized = quantize(&values, 1.0 / 127.0, 0, i8::MIN as i32, i8::MAX as i32);
+
+        assert_eq!(quantized, vec![-127, 0, 64, 127]);
+        for (x, y) in values.iter().zip(dequantize(&quantized, 1.0 / 127.0, 0)) {
+            assert!((x - y).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn uint8_is_clamped() {
+        assert_eq!(quantize(&[-10.0, 300.0], 1.0, 0, 0, 255), vec![0, 255]);
+        assert_eq!(quantize(&[0.0], 0.5, 128, 0, 255), vec![128]);
+    }
+}
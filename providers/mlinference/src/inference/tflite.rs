@@ -1,6 +1,10 @@
-use crate::inference::{
-    ExecutionTarget, Graph, GraphEncoding, GraphExecutionContext, InferenceEngine, InferenceError,
-    InferenceResult,
+use crate::{
+    inference::{
+        bytes_to_f32_vec, dequantize, quantization, quantize, ExecutionTarget, Graph,
+        GraphEncoding, GraphExecutionContext, InferenceEngine, InferenceError, InferenceResult,
+        TfLiteOptions,
+    },
+    ModelMetadata,
 };
 use async_trait::async_trait;
 #[cfg(feature = "edgetpu")]
@@ -23,6 +27,9 @@ pub struct TfLiteEngine<'a> {
 pub struct ModelState<'a> {
     executions: BTreeMap<GraphExecutionContext, TfLiteSession<'a, BuiltinOpResolver>>,
     models: BTreeMap<Graph, Vec<u8>>,
+    /// graph of the next loaded model, graphs are not reused once dropped
+    next_graph: Graph,
+    options: BTreeMap<Graph, TfLiteOptions>,
 }
 
 impl<'a> ModelState<'a> {
@@ -43,6 +50,8 @@ pub struct TfLiteSession<'a, BuiltinOpResolver: OpResolver> {
     pub encoding: GraphEncoding,
     pub input_tensors: usize,
     pub output_tensors: Option<Vec<Tensor>>,
+    /// quantized outputs are returned as raw bytes rather than dequantized
+    pub raw_output: bool,
     #[cfg(feature = "edgetpu")]
     pub edgetpu_context: Option<edgetpu::EdgeTpuContext>,
 }
@@ -61,18 +70,29 @@ impl<'a> TfLiteSession<'a, BuiltinOpResolver> {
             edgetpu_context,
             input_tensors: 0,
             output_tensors: None,
+            raw_output: false,
         }
     }
 }
 
 #[async_trait]
 impl<'a> InferenceEngine for TfLiteEngine<'a> {
+    /// priority, the Edge TPU requires feature 'edgetpu'
+    fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+        match (encoding, target) {
+            (GraphEncoding::TfLite, ExecutionTarget::Cpu) => Some(100),
+            (GraphEncoding::TfLite, ExecutionTarget::Tpu) if cfg!(feature = "edgetpu") => Some(100),
+            _ => None,
+        }
+    }
+
     /// load
     async fn load(&self, model: &[u8]) -> InferenceResult<Graph> {
         let model_bytes = model.to_vec();
 
         let mut state = self.state.write().await;
-        let graph = state.key(state.models.keys());
+        let graph = state.next_graph;
+        state.next_graph += 1;
 
         log::debug!(
             "load() - inserting graph: {:#?} with size {:#?}",
@@ -90,6 +110,17 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         Ok(graph)
     }
 
+    /// configure
+    async fn configure(&self, graph: Graph, metadata: &ModelMetadata) -> InferenceResult<()> {
+        let options = metadata.tflite.clone().unwrap_or_default();
+
+        log::debug!("configure() - graph {:#?} has options {:?}", graph, options);
+
+        let mut state = self.state.write().await;
+        state.options.insert(graph, options);
+        Ok(())
+    }
+
     /// init execution context
     async fn init_execution_context(
         &self,
@@ -119,6 +150,7 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         }
 
         let mut state = self.state.write().await;
+        let options = state.options.get(&graph).cloned().unwrap_or_default();
         let model_bytes = match state.models.get(&graph) {
             Some(mb) => mb,
             None => {
@@ -165,6 +197,10 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
             InferenceError::InterpreterBuildError
         })?;
 
+        if let Some(threads) = options.threads {
+            interpreter.set_num_threads(threads);
+        }
+
         #[cfg(feature = "edgetpu")]        
         let mut edgetpu_context: Option<edgetpu::EdgeTpuContext> = None;
 
@@ -172,7 +208,7 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         if matches!(target, &ExecutionTarget::Tpu) {
             edgetpu_context = Some(EdgeTpuContext::open_device().map_err(|_| {
                 log::error!("init_execution_context() - failed to get edge TPU context");
-                InferenceError::FailedToBuildModelFromBuffer
+                InferenceError::DeviceNotFound(ExecutionTarget::Tpu)
             })?);
 
             interpreter.set_external_context(
@@ -196,15 +232,18 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
             gec
         );
 
-        state.executions.insert(
-            gec,
-            TfLiteSession::with_graph(
-                interpreter, 
-                encoding.to_owned(), 
-                #[cfg(feature = "edgetpu")]                
-                edgetpu_context
-            ),
+        let mut session = TfLiteSession::with_graph(
+            interpreter, 
+            encoding.to_owned(), 
+            #[cfg(feature = "edgetpu")]                
+            edgetpu_context
         );
+        session.raw_output = options.raw_output.unwrap_or(false);
+
+        state.executions.insert(gec, session);
+
+        // the raw model is not needed once its session is built
+        state.models.remove(&graph);
 
         log::debug!("init_execution_context() - passed");
 
@@ -242,16 +281,56 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         // prepare for multiple input tensors
         execution.input_tensors = execution.input_tensors + 1;
 
-        log::debug!(
-            "set_input() - required shape: {:?}",
-            execution.graph.tensor_info(tensor_index).unwrap().dims,
-        );
+        let info = execution.graph.tensor_info(tensor_index).ok_or_else(|| {
+            log::error!("set_input() - info for input tensor could not be evaluated");
+            InferenceError::RuntimeError
+        })?;
+
+        log::debug!("set_input() - required shape: {:?}", info.dims);
+
+        // f32 inputs of quantized models are quantized by the input's parameters,
+        // any other input is copied as is
+        let f32_input = matches!(tensor.value_types.first(), Some(ValueType::ValueF32));
+        let (scale, zero_point) = quantization(info.params.scale, info.params.zero_point);
 
-        execution
+        let bytes: Vec<u8> = match info.element_kind {
+            tflite::context::ElementKind::kTfLiteUInt8 if f32_input => {
+                let values = bytes_to_f32_vec(tensor.data.clone()).await?;
+                quantize(&values, scale, zero_point, u8::MIN as i32, u8::MAX as i32)
+                    .into_iter()
+                    .map(|q| q as u8)
+                    .collect()
+            }
+            tflite::context::ElementKind::kTfLiteInt8 if f32_input => {
+                let values = bytes_to_f32_vec(tensor.data.clone()).await?;
+                quantize(&values, scale, zero_point, i8::MIN as i32, i8::MAX as i32)
+                    .into_iter()
+                    .map(|q| q as i8 as u8)
+                    .collect()
+            }
+            tflite::context::ElementKind::kTfLiteFloat32
+            | tflite::context::ElementKind::kTfLiteUInt8
+            | tflite::context::ElementKind::kTfLiteInt8 => tensor.data.clone(),
+            kind => {
+                log::error!("set_input() - unsupported input type {:?}", kind);
+                return Err(InferenceError::CorruptInputTensor);
+            }
+        };
+
+        let buffer = execution
             .graph
-            .tensor_data_mut(tensor_index)
-            .unwrap()
-            .copy_from_slice(tensor.data.as_slice());
+            .tensor_buffer_mut(tensor_index)
+            .ok_or(InferenceError::CorruptInputTensor)?;
+
+        if buffer.len() != bytes.len() {
+            log::error!(
+                "set_input() - input has {} bytes, the model expects {}",
+                bytes.len(),
+                buffer.len()
+            );
+            return Err(InferenceError::CorruptInputTensor);
+        }
+        buffer.copy_from_slice(&bytes);
 
         log::debug!("set_input() - passed");
 
@@ -274,6 +353,7 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
             }
         };
 
+        let raw_output = execution.raw_output;
         let interpreter = &mut execution.graph;
 
         interpreter.invoke().map_err(|_| {
@@ -291,42 +371,58 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         let mut result_tensors: Vec<Tensor> = Vec::new();
 
         for &output in output_tensors {
-            let mut results = Vec::new();
             let tensor_info = interpreter.tensor_info(output).ok_or_else(|| {
                 log::error!("compute() - info for output tensor could not be evaluated");
                 return InferenceError::RuntimeError;
             })?;
 
-            match tensor_info.element_kind {
-                tflite::context::ElementKind::kTfLiteUInt8 => {
-                    let out_tensor: &[u8] = interpreter.tensor_data(output).map_err(|_| {
+            let (scale, zero_point) =
+                quantization(tensor_info.params.scale, tensor_info.params.zero_point);
+
+            let (value_type, bytes) = match tensor_info.element_kind {
+                tflite::context::ElementKind::kTfLiteUInt8
+                | tflite::context::ElementKind::kTfLiteInt8 => {
+                    let out_tensor: &[u8] = interpreter.tensor_buffer(output).ok_or_else(|| {
                         log::error!("compute() - failed to get output tensor");
                         InferenceError::FailedToBuildModelFromBuffer
                     })?;
-                    let scale = tensor_info.params.scale;
-                    let zero_point = tensor_info.params.zero_point;
-                    results = out_tensor
-                        .into_iter()
-                        .map(|&x| scale * (((x as i32) - zero_point) as f32))
-                        .collect();
+
+                    if raw_output {
+                        let value_type = match tensor_info.element_kind {
+                            tflite::context::ElementKind::kTfLiteInt8 => ValueType::ValueS8,
+                            _ => ValueType::ValueU8,
+                        };
+                        (value_type, out_tensor.to_vec())
+                    } else {
+                        let quantized: Vec<i32> = match tensor_info.element_kind {
+                            tflite::context::ElementKind::kTfLiteInt8 => {
+                                out_tensor.iter().map(|&x| x as i8 as i32).collect()
+                            }
+                            _ => out_tensor.iter().map(|&x| x as i32).collect(),
+                        };
+                        let results = dequantize(&quantized, scale, zero_point);
+                        (ValueType::ValueF32, f32_vec_to_bytes(results).await)
+                    }
                 }
                 tflite::context::ElementKind::kTfLiteFloat32 => {
                     let out_tensor: &[f32] = interpreter.tensor_data(output).map_err(|_| {
                         log::error!("compute() - failed to get output tensor");
                         InferenceError::FailedToBuildModelFromBuffer
                     })?;
-                    results = out_tensor.into_iter().copied().collect();
+                    let results = out_tensor.into_iter().copied().collect();
+                    (ValueType::ValueF32, f32_vec_to_bytes(results).await)
                 }
-                _ => eprintln!(
-                    "Tensor {} has unsupported output type {:?}.",
-                    tensor_info.name, tensor_info.element_kind,
-                ),
-            }
-
-            let bytes = f32_vec_to_bytes(results).await;
+                _ => {
+                    log::error!(
+                        "compute() - tensor {} has unsupported output type {:?}",
+                        tensor_info.name, tensor_info.element_kind,
+                    );
+                    return Err(InferenceError::RuntimeError);
+                }
+            };
 
             let result_tensor = Tensor {
-                value_types: vec![ValueType::ValueF32],
+                value_types: vec![value_type],
                 dimensions: tensor_info.dims.into_iter().map(|i| i as u32).collect(),
                 flags: TENSOR_FLAG_ROW_MAJOR,
                 data: bytes,
@@ -394,6 +490,7 @@ impl<'a> InferenceEngine for TfLiteEngine<'a> {
         let mut state = self.state.write().await;
 
         state.models.remove(graph);
+        state.options.remove(graph);
         state.executions.remove(gec);
     }
 }
@@ -1,6 +1,9 @@
-use crate::inference::{
-    ExecutionTarget, Graph, GraphEncoding, GraphExecutionContext, InferenceEngine, InferenceError,
-    InferenceResult,
+use crate::{
+    inference::{
+        Dimension, ExecutionTarget, Graph, GraphEncoding, GraphExecutionContext, InferenceEngine,
+        InferenceError, InferenceResult,
+    },
+    ModelMetadata,
 };
 use async_trait::async_trait;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -12,26 +15,33 @@ use std::{
 use tokio::sync::RwLock;
 use tract_onnx::{
     prelude::{Graph as TractGraph, Tensor as TractTensor, *},
-    tract_hir::infer::InferenceOp,
+    tract_hir::infer::{Factoid, InferenceOp},
+    WithOnnx,
 };
 use tract_tensorflow;
 use wasmcloud_interface_mlinference::{
     InferenceOutput, Status, Tensor, ValueType, TENSOR_FLAG_ROW_MAJOR,
 };
 
+/// A model as loaded by tract
+#[derive(Debug)]
+pub enum TractModel {
+    /// ONNX and Tensorflow models, typed and optimized once the input shapes are set
+    Inference(TractGraph<InferenceFact, Box<dyn InferenceOp>>),
+    /// NNEF models and models with a configured input shape, optimized at load time
+    Typed(TypedRunnableModel<TypedModel>),
+}
+
 #[derive(Debug)]
 pub struct TractSession {
-    pub graph: TractGraph<InferenceFact, Box<dyn InferenceOp>>,
+    pub graph: TractModel,
     pub encoding: GraphEncoding,
     pub input_tensors: Option<Vec<TractTensor>>,
     pub output_tensors: Option<Vec<Arc<TractTensor>>>,
 }
 
 impl TractSession {
-    pub fn with_graph(
-        graph: TractGraph<InferenceFact, Box<dyn InferenceOp>>,
-        encoding: GraphEncoding,
-    ) -> Self {
+    pub fn with_graph(graph: TractModel, encoding: GraphEncoding) -> Self {
         Self {
             graph,
             encoding,
@@ -50,6 +60,10 @@ pub struct TractEngine {
 pub struct ModelState {
     executions: BTreeMap<GraphExecutionContext, TractSession>,
     models: BTreeMap<Graph, Vec<u8>>,
+    /// graph of the next loaded model, graphs are not reused once dropped
+    next_graph: Graph,
+    /// configured shapes of the inputs by their index
+    shapes: BTreeMap<Graph, Vec<Vec<Dimension>>>,
 }
 
 impl ModelState {
@@ -67,11 +81,23 @@ impl ModelState {
 
 #[async_trait]
 impl InferenceEngine for TractEngine {
+    /// priority
+    fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+        match (encoding, target) {
+            (
+                GraphEncoding::Onnx | GraphEncoding::Tensorflow | GraphEncoding::Nnef,
+                ExecutionTarget::Cpu,
+            ) => Some(100),
+            _ => None,
+        }
+    }
+
     /// load
     async fn load(&self, model: &[u8]) -> InferenceResult<Graph> {
         let model_bytes = model.to_vec();
         let mut state = self.state.write().await;
-        let graph = state.key(state.models.keys());
+        let graph = state.next_graph;
+        state.next_graph += 1;
 
         log::debug!(
             "load() - inserting graph: {:#?} with size {:#?}",
@@ -89,6 +115,21 @@ impl InferenceEngine for TractEngine {
         Ok(graph)
     }
 
+    /// configure
+    async fn configure(&self, graph: Graph, metadata: &ModelMetadata) -> InferenceResult<()> {
+        if let Some(shapes) = metadata.input_shapes() {
+            log::debug!(
+                "configure() - graph {:#?} has input shapes {:?}",
+                graph,
+                shapes
+            );
+
+            let mut state = self.state.write().await;
+            state.shapes.insert(graph, shapes);
+        }
+        Ok(())
+    }
+
     /// init_execution_context
     async fn init_execution_context(
         &self,
@@ -128,12 +169,38 @@ impl InferenceEngine for TractEngine {
             }
         };
 
-        let model = match encoding {
-            GraphEncoding::Onnx => tract_onnx::onnx().model_for_read(&mut model_bytes).unwrap(),
+        let mut model = match encoding {
+            GraphEncoding::Onnx => {
+                TractModel::Inference(tract_onnx::onnx().model_for_read(&mut model_bytes).unwrap())
+            }
 
-            GraphEncoding::Tensorflow => tract_tensorflow::tensorflow()
-                .model_for_read(&mut model_bytes)
-                .unwrap(),
+            GraphEncoding::Tensorflow => TractModel::Inference(
+                tract_tensorflow::tensorflow()
+                    .model_for_read(&mut model_bytes)
+                    .unwrap(),
+            ),
+
+            // NNEF archives (.nnef.tar or .nnef.tgz) carry fully typed graphs,
+            // hence they are optimized right away instead of on each compute()
+            GraphEncoding::Nnef => {
+                let nnef = tract_nnef::nnef().with_tract_core().with_onnx();
+                let model = nnef
+                    .model_for_read(&mut model_bytes)
+                    .and_then(|m| m.into_optimized())
+                    .and_then(|m| m.into_runnable());
+
+                match model {
+                    Ok(m) => TractModel::Typed(m),
+                    Err(e) => {
+                        log::error!(
+                            "init_execution_context() - cannot load NNEF model of graph {:#?}: {}",
+                            graph,
+                            e
+                        );
+                        return Err(InferenceError::FailedToBuildModelFromBuffer);
+                    }
+                }
+            }
 
             _ => {
                 log::error!(
@@ -144,6 +211,12 @@ impl InferenceEngine for TractEngine {
             }
         };
 
+        if let (TractModel::Inference(inference_model), Some(shapes)) =
+            (&model, state.shapes.get(&graph))
+        {
+            model = optimize_for_shapes(inference_model.clone(), shapes)?;
+        }
+
         let gec = state.key(state.executions.keys());
 
         log::debug!(
@@ -155,6 +228,9 @@ impl InferenceEngine for TractEngine {
             .executions
             .insert(gec, TractSession::with_graph(model, encoding.to_owned()));
 
+        // the raw model is not needed once its session is built
+        state.models.remove(&graph);
+
         Ok(gec)
     }
 
@@ -185,29 +261,44 @@ impl InferenceEngine for TractEngine {
         };
 
         let shape = tensor.shape();
-        execution.graph.set_input_fact(
-            index as usize,
-            InferenceFact::dt_shape(f32::datum_type(), shape.clone()),
-        )?;
+        // the input facts of typed models are fixed, mismatching inputs are reported by compute()
+        let datum_type = match execution.graph {
+            TractModel::Inference(ref mut graph) => {
+                let datum_type = declared_datum_type(graph, index as usize)?;
+                graph.set_input_fact(
+                    index as usize,
+                    InferenceFact::dt_shape(datum_type, shape.clone()),
+                )?;
+                datum_type
+            }
+            TractModel::Typed(ref runnable) => {
+                runnable.model().input_fact(index as usize)?.datum_type
+            }
+        };
 
+        // inputs are sent as f32 and cast to the type the model declares, e.g. i64 token ids
         let data: Vec<f32> = bytes_to_f32_vec(tensor.data.as_slice().to_vec()).await?;
         let input: TractTensor = Array::from_shape_vec(shape, data)?.into();
+        let input = input.cast_to_dt(datum_type)?.into_owned();
 
-        match execution.input_tensors {
-            Some(ref mut input_arrays) => {
-                // __CB__2022-03-10 re-evaluate next line
-                input_arrays.clear();
-                input_arrays.push(input);
-
-                log::debug!(
-                    "set_input() - input arrays now contains {} items",
-                    input_arrays.len(),
+        let input_arrays = execution.input_tensors.get_or_insert_with(Vec::new);
+        match (index as usize).cmp(&input_arrays.len()) {
+            std::cmp::Ordering::Less => input_arrays[index as usize] = input,
+            std::cmp::Ordering::Equal => input_arrays.push(input),
+            std::cmp::Ordering::Greater => {
+                log::error!(
+                    "set_input() - input {} is set before input {}",
+                    index,
+                    input_arrays.len()
                 );
+                return Err(InferenceError::RuntimeError);
             }
-            None => {
-                execution.input_tensors = Some(vec![input]);
-            }
-        };
+        }
+
+        log::debug!(
+            "set_input() - input arrays now contains {} items",
+            input_arrays.len(),
+        );
         Ok(())
     }
 
@@ -247,12 +338,15 @@ impl InferenceEngine for TractEngine {
         // Some ONNX models don't specify their input tensor
         // shapes completely, so we can only call `.into_optimized()` after we
         // have set the input tensor shapes.
-        let output_tensors = execution
-            .graph
-            .clone()
-            .into_optimized()?
-            .into_runnable()?
-            .run(input_tensors.into())?;
+        let output_tensors = match execution.graph {
+            TractModel::Inference(ref graph) => graph
+                .clone()
+                .into_optimized()?
+                .into_runnable()?
+                .run(input_tensors.into())?,
+
+            TractModel::Typed(ref runnable) => runnable.run(input_tensors.into())?,
+        };
 
         log::debug!(
             "compute() - output tensors contains {} elements",
@@ -319,7 +413,9 @@ impl InferenceEngine for TractEngine {
             }
         };
 
-        let bytes = f32_array_to_bytes(tensor.as_slice().unwrap()).await;
+        // outputs of other types, e.g. i64 token ids, are returned as f32 like the inputs
+        let values = tensor.cast_to::<f32>()?;
+        let bytes = f32_array_to_bytes(values.as_slice::<f32>()?).await;
 
         let io = InferenceOutput {
             result: Status::Success,
@@ -343,10 +439,60 @@ impl InferenceEngine for TractEngine {
         let mut state = self.state.write().await;
 
         state.models.remove(graph);
+        state.shapes.remove(graph);
         state.executions.remove(gec);
     }
 }
 
+/// Fixes the inputs to their configured shapes, symbols included, such that
+/// the model is optimized once rather than on each compute(). Each input keeps
+/// the datum type the model declares.
+fn optimize_for_shapes(
+    mut model: TractGraph<InferenceFact, Box<dyn InferenceOp>>,
+    shapes: &[Vec<Dimension>],
+) -> InferenceResult<TractModel> {
+    for (input, shape) in shapes.iter().enumerate() {
+        let dims = shape
+            .iter()
+            .map(|d| match d {
+                Dimension::Fixed(n) => Ok(TDim::from(*n as i64)),
+                Dimension::Symbol(s) => {
+                    let mut chars = s.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if c.is_alphabetic() => Ok(TDim::from(Symbol::from(c))),
+                        _ => Err(InferenceError::InvalidShape(format!(
+                            "symbol '{}' must be a single letter",
+                            s
+                        ))),
+                    }
+                }
+            })
+            .collect::<InferenceResult<TVec<TDim>>>()?;
+
+        let datum_type = declared_datum_type(&model, input)?;
+        model.set_input_fact(input, InferenceFact::dt_shape(datum_type, dims))?;
+    }
+
+    // outputs declared of a fixed size, e.g. a batch of 1, would conflict with the symbols
+    for output in 0..model.outputs.len() {
+        model.set_output_fact(output, InferenceFact::default())?;
+    }
+
+    Ok(TractModel::Typed(model.into_optimized()?.into_runnable()?))
+}
+
+/// datum type the model declares for an input, f32 if it declares none
+fn declared_datum_type(
+    model: &TractGraph<InferenceFact, Box<dyn InferenceOp>>,
+    input: usize,
+) -> InferenceResult<DatumType> {
+    Ok(model
+        .input_fact(input)?
+        .datum_type
+        .concretize()
+        .unwrap_or_else(f32::datum_type))
+}
+
 pub type Result<T> = std::io::Result<T>;
 
 pub async fn bytes_to_f32_vec(data: Vec<u8>) -> Result<Vec<f32>> {
@@ -384,3 +530,94 @@ pub async fn f32_array_to_bytes(values: &[f32]) -> Vec<u8> {
 //     );
 //     result
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn symbolic_batch_size_is_optimized_once() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let engine = TractEngine::default();
+        let metadata = ModelMetadata {
+            graph_encoding: GraphEncoding::Onnx,
+            tensor_shape_in: Some(vec![
+                Dimension::Symbol("N".to_string()),
+                Dimension::Fixed(4),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = engine.load(&onnx).await.unwrap();
+        engine.configure(graph, &metadata).await.unwrap();
+        let gec = engine
+            .init_execution_context(graph, &ExecutionTarget::Cpu, &GraphEncoding::Onnx)
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.state.read().await.executions[&gec].graph,
+            TractModel::Typed(_)
+        ));
+
+        for batch in [1, 3] {
+            let values: Vec<f32> = (0..batch * 4).map(|v| v as f32).collect();
+            let input = Tensor {
+                value_types: vec![ValueType::ValueF32],
+                dimensions: vec![batch, 4],
+                flags: TENSOR_FLAG_ROW_MAJOR,
+                data: f32_array_to_bytes(&values).await,
+            };
+            engine.set_input(gec, 0, &input).await.unwrap();
+            engine.compute(gec).await.unwrap();
+            let output = engine.get_output(gec, 0).await.unwrap();
+
+            assert_eq!(output.tensor.dimensions, vec![batch, 4]);
+            assert_eq!(output.tensor.data, input.data);
+        }
+    }
+
+    #[tokio::test]
+    async fn nnef_archives_run() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let typed = tract_onnx::onnx()
+            .model_for_read(&mut Cursor::new(onnx))
+            .unwrap()
+            .with_input_fact(0, InferenceFact::dt_shape(f32::datum_type(), tvec!(1, 4)))
+            .unwrap()
+            .into_typed()
+            .unwrap()
+            .into_decluttered()
+            .unwrap();
+
+        // written like 'mlinference-pack' does, as '.nnef.tgz'
+        let mut archive = Vec::new();
+        let encoder = flate2::write::GzEncoder::new(&mut archive, flate2::Compression::default());
+        tract_nnef::nnef()
+            .with_tract_core()
+            .with_onnx()
+            .write_to_tar(&typed, encoder)
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        let engine = TractEngine::default();
+        let graph = engine.load(&archive).await.unwrap();
+        let gec = engine
+            .init_execution_context(graph, &ExecutionTarget::Cpu, &GraphEncoding::Nnef)
+            .await
+            .unwrap();
+
+        let input = Tensor {
+            value_types: vec![ValueType::ValueF32],
+            dimensions: vec![1, 4],
+            flags: TENSOR_FLAG_ROW_MAJOR,
+            data: f32_array_to_bytes(&[1.0, 2.0, 3.0, 4.0]).await,
+        };
+        engine.set_input(gec, 0, &input).await.unwrap();
+        engine.compute(gec).await.unwrap();
+        let output = engine.get_output(gec, 0).await.unwrap();
+
+        assert_eq!(output.tensor.dimensions, vec![1, 4]);
+        assert_eq!(output.tensor.data, input.data);
+    }
+}
@@ -0,0 +1,542 @@
+use crate::{
+    inference::{
+        bytes_to_f32_vec, dequantize, f32_array_to_bytes, quantization, quantize, ExecutionTarget,
+        Graph, GraphEncoding, GraphExecutionContext, InferenceEngine, InferenceError,
+        InferenceResult, TfLiteOptions,
+    },
+    ModelMetadata,
+};
+use async_trait::async_trait;
+use std::{
+    collections::{btree_map::Keys, BTreeMap},
+    io::Cursor,
+};
+use tokio::sync::RwLock;
+use tract_core_tflite::prelude::{Tensor as TractTensor, *};
+use wasmcloud_interface_mlinference::{
+    InferenceOutput, Status, Tensor, ValueType, TENSOR_FLAG_ROW_MAJOR,
+};
+
+/// A TfLite model run by tract's tflite frontend
+#[derive(Debug)]
+pub struct TractTfLiteSession {
+    pub graph: TypedRunnableModel<TypedModel>,
+    /// datum types of the model's inputs, quantized types included
+    pub input_types: Vec<DatumType>,
+    pub input_tensors: Option<Vec<TractTensor>>,
+    pub output_tensors: Option<Vec<Tensor>>,
+    /// quantized outputs are returned as raw bytes rather than dequantized
+    pub raw_output: bool,
+}
+
+/// Runs TfLite models in pure Rust, used when the native TfLite engine is not compiled in
+#[derive(Default, Clone)]
+pub struct TractTfLiteEngine {
+    state: Arc<RwLock<ModelState>>,
+}
+
+#[derive(Default)]
+pub struct ModelState {
+    executions: BTreeMap<GraphExecutionContext, TractTfLiteSession>,
+    models: BTreeMap<Graph, Vec<u8>>,
+    /// graph of the next loaded model, graphs are not reused once dropped
+    next_graph: Graph,
+    options: BTreeMap<Graph, TfLiteOptions>,
+}
+
+impl ModelState {
+    /// Helper function that returns the key that is supposed to be inserted next.
+    pub fn key<K: Into<u32> + From<u32> + Copy, V>(&self, keys: Keys<K, V>) -> K {
+        match keys.last() {
+            Some(&k) => {
+                let last: u32 = k.into();
+                K::from(last + 1)
+            }
+            None => K::from(0),
+        }
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for TractTfLiteEngine {
+    /// priority, below the native TfLite engine
+    fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+        match (encoding, target) {
+            (GraphEncoding::TfLite, ExecutionTarget::Cpu) => Some(50),
+            _ => None,
+        }
+    }
+
+    /// load
+    async fn load(&self, model: &[u8]) -> InferenceResult<Graph> {
+        let mut state = self.state.write().await;
+        let graph = state.next_graph;
+        state.next_graph += 1;
+
+        log::debug!(
+            "load() - inserting graph: {:#?} with size {:#?}",
+            graph,
+            model.len()
+        );
+
+        state.models.insert(graph, model.to_vec());
+        Ok(graph)
+    }
+
+    /// configure
+    async fn configure(&self, graph: Graph, metadata: &ModelMetadata) -> InferenceResult<()> {
+        let options = metadata.tflite.clone().unwrap_or_default();
+
+        if options.threads.is_some() {
+            log::warn!(
+                "configure() - graph {:#?}: 'threads' applies to the native TfLite engine only",
+                graph
+            );
+        }
+
+        let mut state = self.state.write().await;
+        state.options.insert(graph, options);
+        Ok(())
+    }
+
+    /// init_execution_context
+    async fn init_execution_context(
+        &self,
+        graph: Graph,
+        target: &ExecutionTarget,
+        encoding: &GraphEncoding,
+    ) -> InferenceResult<GraphExecutionContext> {
+        if !matches!(target, &ExecutionTarget::Cpu) {
+            log::error!(
+                "This framework does not support execution target '{:?}'",
+                target
+            );
+            return Err(InferenceError::UnsupportedExecutionTarget);
+        }
+
+        if !matches!(encoding, &GraphEncoding::TfLite) {
+            log::error!(
+                "requested encoding '{:?}' is currently not supported",
+                encoding
+            );
+            return Err(InferenceError::InvalidEncodingError);
+        }
+
+        let mut state = self.state.write().await;
+        let mut model_bytes = match state.models.get(&graph) {
+            Some(mb) => Cursor::new(mb),
+            None => {
+                log::error!(
+                    "init_execution_context() - cannot find model in state with graph {:#?}",
+                    graph
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let model = tract_tflite::tflite()
+            .model_for_read(&mut model_bytes)
+            .and_then(|m| {
+                let input_types = (0..m.inputs.len())
+                    .map(|i| m.input_fact(i).map(|f| f.datum_type))
+                    .collect::<TractResult<Vec<DatumType>>>()?;
+                Ok((m.into_optimized()?.into_runnable()?, input_types))
+            });
+
+        let (runnable, input_types) = match model {
+            Ok(m) => m,
+            Err(e) => {
+                log::error!(
+                    "init_execution_context() - cannot load TfLite model of graph {:#?}: {}",
+                    graph,
+                    e
+                );
+                return Err(InferenceError::FailedToBuildModelFromBuffer);
+            }
+        };
+
+        let raw_output = state
+            .options
+            .get(&graph)
+            .and_then(|o| o.raw_output)
+            .unwrap_or(false);
+        let gec = state.key(state.executions.keys());
+
+        log::debug!(
+            "init_execution_context() - inserting graph execution context: {:#?}",
+            gec
+        );
+
+        state.executions.insert(
+            gec,
+            TractTfLiteSession {
+                graph: runnable,
+                input_types,
+                input_tensors: None,
+                output_tensors: None,
+                raw_output,
+            },
+        );
+
+        // the raw model is not needed once its session is built
+        state.models.remove(&graph);
+
+        Ok(gec)
+    }
+
+    /// set_input
+    async fn set_input(
+        &self,
+        context: GraphExecutionContext,
+        index: u32,
+        tensor: &Tensor,
+    ) -> InferenceResult<()> {
+        let mut state = self.state.write().await;
+        let execution = match state.executions.get_mut(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "set_input() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let datum_type = match execution.input_types.get(index as usize) {
+            Some(dt) => *dt,
+            None => {
+                log::error!("set_input() - model has no input {}", index);
+                return Err(InferenceError::CorruptInputTensor);
+            }
+        };
+
+        let shape: Vec<usize> = tensor.dimensions.iter().map(|&d| d as usize).collect();
+        let f32_input = matches!(tensor.value_types.first(), Some(ValueType::ValueF32));
+
+        // f32 inputs of quantized models are quantized by the input's parameters,
+        // u8 inputs are taken as they are, like the native TfLite engine does
+        let input = if f32_input && datum_type.is_quantized() {
+            let (zero_point, scale) = datum_type.zp_scale();
+            let (scale, zero_point) = quantization(scale, zero_point);
+            let (min, max) = match datum_type.unquantized() {
+                DatumType::I8 => (i8::MIN as i32, i8::MAX as i32),
+                _ => (u8::MIN as i32, u8::MAX as i32),
+            };
+            let values = bytes_to_f32_vec(tensor.data.clone()).await?;
+            let bytes: Vec<u8> = quantize(&values, scale, zero_point, min, max)
+                .into_iter()
+                .map(|q| q as u8)
+                .collect();
+            byte_tensor(&shape, &bytes, datum_type)?
+        } else if f32_input {
+            let values = bytes_to_f32_vec(tensor.data.clone()).await?;
+            TractTensor::from_shape(&shape, &values)?
+        } else {
+            byte_tensor(&shape, &tensor.data, datum_type)?
+        };
+
+        let input_tensors = execution.input_tensors.get_or_insert_with(Vec::new);
+        match (index as usize).cmp(&input_tensors.len()) {
+            std::cmp::Ordering::Less => input_tensors[index as usize] = input,
+            std::cmp::Ordering::Equal => input_tensors.push(input),
+            std::cmp::Ordering::Greater => {
+                log::error!(
+                    "set_input() - input {} is set before input {}",
+                    index,
+                    input_tensors.len()
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// compute()
+    async fn compute(&self, context: GraphExecutionContext) -> InferenceResult<()> {
+        let mut state = self.state.write().await;
+        let execution = match state.executions.get_mut(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "compute() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let inputs: TVec<TValue> = execution
+            .input_tensors
+            .iter()
+            .flatten()
+            .map(|t| t.clone().into())
+            .collect();
+
+        // values share their tensors by Rc, they are not held across the awaits below
+        let outputs: Vec<TractTensor> = execution
+            .graph
+            .run(inputs)?
+            .into_iter()
+            .map(|v| v.into_tensor())
+            .collect();
+
+        log::debug!(
+            "compute() - output tensors contains {} elements",
+            outputs.len()
+        );
+
+        let mut result_tensors: Vec<Tensor> = Vec::new();
+
+        for output in outputs.iter() {
+            let datum_type = output.datum_type();
+
+            let (value_type, bytes) = match datum_type.unquantized() {
+                DatumType::F32 => (
+                    ValueType::ValueF32,
+                    f32_array_to_bytes(output.as_slice::<f32>()?).await,
+                ),
+                DatumType::U8 if execution.raw_output => {
+                    (ValueType::ValueU8, output.as_bytes().to_vec())
+                }
+                DatumType::I8 if execution.raw_output => {
+                    (ValueType::ValueS8, output.as_bytes().to_vec())
+                }
+                DatumType::U8 | DatumType::I8 => {
+                    let (zero_point, scale) = datum_type.zp_scale();
+                    let (scale, zero_point) = quantization(scale, zero_point);
+                    let quantized: Vec<i32> = match datum_type.unquantized() {
+                        DatumType::I8 => {
+                            output.as_bytes().iter().map(|&x| x as i8 as i32).collect()
+                        }
+                        _ => output.as_bytes().iter().map(|&x| x as i32).collect(),
+                    };
+                    let results = dequantize(&quantized, scale, zero_point);
+                    (ValueType::ValueF32, f32_array_to_bytes(&results).await)
+                }
+                _ => {
+                    log::error!("compute() - unsupported output type {:?}", datum_type);
+                    return Err(InferenceError::RuntimeError);
+                }
+            };
+
+            result_tensors.push(Tensor {
+                value_types: vec![value_type],
+                dimensions: output.shape().iter().map(|&d| d as u32).collect(),
+                flags: TENSOR_FLAG_ROW_MAJOR,
+                data: bytes,
+            });
+        }
+
+        execution.output_tensors.replace(result_tensors);
+
+        Ok(())
+    }
+
+    /// get_output
+    async fn get_output(
+        &self,
+        context: GraphExecutionContext,
+        index: u32,
+    ) -> InferenceResult<InferenceOutput> {
+        let state = self.state.read().await;
+        let execution = match state.executions.get(&context) {
+            Some(s) => s,
+            None => {
+                log::error!(
+                    "get_output() - cannot find session in state with context {:#?}",
+                    context
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        let tensor = match execution
+            .output_tensors
+            .as_ref()
+            .and_then(|o| o.get(index as usize))
+        {
+            Some(t) => t.clone(),
+            None => {
+                log::error!(
+                    "get_output() - no output tensor {}, perhaps you haven't called compute yet?",
+                    index
+                );
+                return Err(InferenceError::RuntimeError);
+            }
+        };
+
+        Ok(InferenceOutput {
+            result: Status::Success,
+            tensor,
+        })
+    }
+
+    /// remove model state
+    async fn drop_model_state(&self, graph: &Graph, gec: &GraphExecutionContext) {
+        let mut state = self.state.write().await;
+
+        state.models.remove(graph);
+        state.options.remove(graph);
+        state.executions.remove(gec);
+    }
+}
+
+/// A tensor of 'datum_type' holding the bytes of a u8 or i8 input. The bytes
+/// are reinterpreted, which holds for their quantized types being of the same size.
+fn byte_tensor(
+    shape: &[usize],
+    bytes: &[u8],
+    datum_type: DatumType,
+) -> InferenceResult<TractTensor> {
+    if !matches!(datum_type.unquantized(), DatumType::U8 | DatumType::I8) {
+        log::error!(
+            "set_input() - byte input given for model input of type {:?}",
+            datum_type
+        );
+        return Err(InferenceError::CorruptInputTensor);
+    }
+
+    let mut tensor = TractTensor::from_shape(shape, bytes)?;
+    unsafe { tensor.set_datum_type(datum_type) };
+    Ok(tensor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tract_core_tflite::ops::math;
+
+    /// a TfLite model of inputs 'a' and 'b' of shape [1, 4] returning their sum,
+    /// written by tract, whose reader refuses the unaligned models of the bindles
+    fn sum_model(datum_type: DatumType, b: Option<TractTensor>) -> Vec<u8> {
+        let mut model = TypedModel::default();
+        let a = model.add_source("a", datum_type.fact([1, 4])).unwrap();
+        let b = match b {
+            Some(constant) => model.add_const("b", constant).unwrap(),
+            None => model.add_source("b", datum_type.fact([1, 4])).unwrap(),
+        };
+        let sum = model.wire_node("sum", math::add(), &[a, b]).unwrap();
+        model.set_output_outlets(&sum).unwrap();
+
+        let mut bytes = vec![];
+        tract_tflite::tflite().write(&model, &mut bytes).unwrap();
+        bytes
+    }
+
+    async fn session(model: &[u8], raw_output: bool) -> (TractTfLiteEngine, GraphExecutionContext) {
+        let engine = TractTfLiteEngine::default();
+        let metadata = ModelMetadata {
+            tflite: Some(TfLiteOptions {
+                raw_output: Some(raw_output),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let graph = engine.load(model).await.unwrap();
+        engine.configure(graph, &metadata).await.unwrap();
+        let gec = engine
+            .init_execution_context(graph, &ExecutionTarget::Cpu, &GraphEncoding::TfLite)
+            .await
+            .unwrap();
+
+        (engine, gec)
+    }
+
+    fn f32_tensor(values: &[f32]) -> Tensor {
+        Tensor {
+            value_types: vec![ValueType::ValueF32],
+            dimensions: vec![1, values.len() as u32],
+            flags: TENSOR_FLAG_ROW_MAJOR,
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    fn f32_values(tensor: &Tensor) -> Vec<f32> {
+        tensor
+            .data
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn inputs_are_set_by_index() {
+        let (engine, gec) = session(&sum_model(f32::datum_type(), None), false).await;
+
+        engine
+            .set_input(gec, 0, &f32_tensor(&[1.0, 2.0, 3.0, 4.0]))
+            .await
+            .unwrap();
+        engine
+            .set_input(gec, 1, &f32_tensor(&[10.0, 20.0, 30.0, 40.0]))
+            .await
+            .unwrap();
+        engine.compute(gec).await.unwrap();
+        let output = engine.get_output(gec, 0).await.unwrap();
+
+        assert_eq!(output.tensor.dimensions, vec![1, 4]);
+        assert_eq!(f32_values(&output.tensor), vec![11.0, 22.0, 33.0, 44.0]);
+    }
+
+    #[tokio::test]
+    async fn quantized_outputs_are_dequantized_unless_raw() {
+        let qu8 = u8::datum_type().quantize(QParams::ZpScale {
+            zero_point: 128,
+            scale: 0.5,
+        });
+        let mut b = tensor2(&[[130u8, 132, 134, 136]]);
+        unsafe { b.set_datum_type(qu8) };
+        let model = sum_model(qu8, Some(b));
+        let input = f32_tensor(&[0.0, 0.0, 0.0, 0.0]);
+
+        let (engine, gec) = session(&model, false).await;
+        engine.set_input(gec, 0, &input).await.unwrap();
+        engine.compute(gec).await.unwrap();
+        let output = engine.get_output(gec, 0).await.unwrap();
+
+        assert!(matches!(
+            output.tensor.value_types[..],
+            [ValueType::ValueF32]
+        ));
+        assert_eq!(f32_values(&output.tensor), vec![1.0, 2.0, 3.0, 4.0]);
+
+        let (engine, gec) = session(&model, true).await;
+        engine.set_input(gec, 0, &input).await.unwrap();
+        engine.compute(gec).await.unwrap();
+        let output = engine.get_output(gec, 0).await.unwrap();
+
+        assert!(matches!(
+            output.tensor.value_types[..],
+            [ValueType::ValueU8]
+        ));
+        assert_eq!(output.tensor.data, vec![130, 132, 134, 136]);
+    }
+
+    #[tokio::test]
+    async fn raw_int8_outputs_are_signed() {
+        let qi8 = i8::datum_type().quantize(QParams::ZpScale {
+            zero_point: 0,
+            scale: 0.5,
+        });
+        let mut b = tensor2(&[[-2i8, -4, 6, 8]]);
+        unsafe { b.set_datum_type(qi8) };
+        let model = sum_model(qi8, Some(b));
+
+        let (engine, gec) = session(&model, true).await;
+        engine
+            .set_input(gec, 0, &f32_tensor(&[0.0, 0.0, 0.0, 0.0]))
+            .await
+            .unwrap();
+        engine.compute(gec).await.unwrap();
+        let output = engine.get_output(gec, 0).await.unwrap();
+
+        assert!(matches!(
+            output.tensor.value_types[..],
+            [ValueType::ValueS8]
+        ));
+        assert_eq!(output.tensor.data, vec![-2i8 as u8, -4i8 as u8, 6, 8]);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{GraphEncoding, ModelMetadata};
+use std::io::Cursor;
+use tract_onnx::{prelude::*, tract_hir::infer::Factoid, WithOnnx};
+
+#[cfg(any(feature = "tflite", feature = "edgetpu"))]
+use tflite::{ops::builtin::BuiltinOpResolver, FlatBufferModel, InterpreterBuilder};
+
+/// Metadata as declared by a model file itself, None where unknown
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeclaredMetadata {
+    pub tensor_type: Option<String>,
+    pub tensor_dimensions_in: Option<Vec<u32>>,
+    pub tensor_dimensions_out: Option<Vec<u32>>,
+}
+
+/// Guesses the encoding of a model from its leading bytes.
+///
+/// ONNX and Tensorflow are both protobuf and are told apart by their first
+/// field, i.e. the 'ir_version' of an ONNX model and the nodes of a graph.
+pub fn detect_encoding(model: &[u8]) -> Option<GraphEncoding> {
+    if model.len() >= 8 && &model[4..8] == b"TFL3" {
+        return Some(GraphEncoding::TfLite);
+    }
+
+    // gzipped or plain tar archive
+    if model.starts_with(&[0x1f, 0x8b]) || model.get(257..262) == Some(&b"ustar"[..]) {
+        return Some(GraphEncoding::Nnef);
+    }
+
+    // little endian header length followed by a json header
+    if model.len() > 8 && model[8] == b'{' {
+        let header_len = u64::from_le_bytes(model[..8].try_into().unwrap());
+        if header_len < model.len() as u64 {
+            return Some(GraphEncoding::Safetensors);
+        }
+    }
+
+    match model.first() {
+        Some(0x08) => Some(GraphEncoding::Onnx),
+        Some(0x0a) => Some(GraphEncoding::Tensorflow),
+        _ => None,
+    }
+}
+
+/// Whether a TfLite model is compiled for the Edge TPU, i.e. contains its custom op
+pub fn requires_edge_tpu(model: &[u8]) -> bool {
+    const EDGETPU_CUSTOM_OP: &[u8] = b"edgetpu-custom-op";
+
+    model
+        .windows(EDGETPU_CUSTOM_OP.len())
+        .any(|w| w == EDGETPU_CUSTOM_OP)
+}
+
+/// Reads tensor type and dimensions of the first input and output as declared
+/// by the model. Unsupported encodings and unreadable models declare nothing.
+pub fn introspect(model: &[u8], encoding: &GraphEncoding) -> DeclaredMetadata {
+    let declared = match encoding {
+        GraphEncoding::Onnx | GraphEncoding::Tensorflow | GraphEncoding::Nnef => {
+            introspect_tract(model, encoding).map_err(|e| e.to_string())
+        }
+
+        #[cfg(any(feature = "tflite", feature = "edgetpu"))]
+        GraphEncoding::TfLite => {
+            introspect_tflite(model).ok_or_else(|| "cannot build interpreter".to_string())
+        }
+
+        _ => Ok(DeclaredMetadata::default()),
+    };
+
+    declared.unwrap_or_else(|e| {
+        log::debug!("introspect() - model of encoding '{:?}': {}", encoding, e);
+        DeclaredMetadata::default()
+    })
+}
+
+/// Completes the shipped metadata (if any) by what the model declares and
+/// warns about shipped values contradicting the model.
+pub fn complete_metadata(
+    model_id: &str,
+    shipped: Option<&[u8]>,
+    model: &[u8],
+) -> Result<ModelMetadata, serde_json::Error> {
+    let (mut metadata, keys) = match shipped {
+        Some(json) => {
+            let keys: Vec<String> = match serde_json::from_slice(json)? {
+                serde_json::Value::Object(map) => map.keys().cloned().collect(),
+                _ => vec![],
+            };
+            (serde_json::from_slice::<ModelMetadata>(json)?, keys)
+        }
+        None => (ModelMetadata::default(), vec![]),
+    };
+    let shipped = |key: &str| keys.iter().any(|k| k == key);
+
+    match detect_encoding(model) {
+        Some(encoding) if !shipped("graph_encoding") => metadata.graph_encoding = encoding,
+        Some(encoding) if encoding != metadata.graph_encoding => log::warn!(
+            "model '{}' looks like '{:?}' but its metadata declares '{:?}'",
+            model_id,
+            encoding,
+            metadata.graph_encoding
+        ),
+        _ => (),
+    }
+
+    let declared = introspect(model, &metadata.graph_encoding);
+
+    if let Some(tensor_type) = declared.tensor_type {
+        if metadata.tensor_type.is_empty() {
+            metadata.tensor_type = tensor_type;
+        } else if !metadata.tensor_type.eq_ignore_ascii_case(&tensor_type) {
+            log::warn!(
+                "model '{}' declares tensor type '{}' but its metadata declares '{}'",
+                model_id,
+                tensor_type,
+                metadata.tensor_type
+            );
+        }
+    }
+
+    complete_dimensions(
+        model_id,
+        "in",
+        &mut metadata.tensor_dimensions_in,
+        declared.tensor_dimensions_in,
+    );
+    complete_dimensions(
+        model_id,
+        "out",
+        &mut metadata.tensor_dimensions_out,
+        declared.tensor_dimensions_out,
+    );
+
+    Ok(metadata)
+}
+
+fn complete_dimensions(
+    model_id: &str,
+    direction: &str,
+    shipped: &mut Option<Vec<u32>>,
+    declared: Option<Vec<u32>>,
+) {
+    match (shipped.as_ref(), declared) {
+        (None, declared) => *shipped = declared,
+        (Some(s), Some(d)) if *s != d => log::warn!(
+            "model '{}' declares dimensions {:?} but its metadata declares tensor_dimensions_{} {:?}",
+            model_id,
+            d,
+            direction,
+            s
+        ),
+        _ => (),
+    }
+}
+
+fn introspect_tract(model: &[u8], encoding: &GraphEncoding) -> TractResult<DeclaredMetadata> {
+    let mut reader = Cursor::new(model);
+
+    let model = match encoding {
+        GraphEncoding::Onnx => tract_onnx::onnx().model_for_read(&mut reader)?,
+        GraphEncoding::Tensorflow => tract_tensorflow::tensorflow().model_for_read(&mut reader)?,
+        GraphEncoding::Nnef => {
+            let typed = tract_nnef::nnef()
+                .with_tract_core()
+                .with_onnx()
+                .model_for_read(&mut reader)?;
+            return declared_by_typed_model(&typed);
+        }
+        _ => return Err(TractError::msg("not a tract encoding")),
+    };
+
+    // typing fails for inputs not fully determined, leaving their fact only
+    let input = model.input_fact(0)?.clone();
+    match model.into_typed() {
+        Ok(typed) => declared_by_typed_model(&typed),
+        Err(_) => Ok(DeclaredMetadata {
+            tensor_type: input.datum_type.concretize().and_then(tensor_type),
+            tensor_dimensions_in: input
+                .shape
+                .as_concrete_finite()?
+                .map(|dims| dims.iter().map(|&d| d as u32).collect()),
+            ..Default::default()
+        }),
+    }
+}
+
+fn declared_by_typed_model(model: &TypedModel) -> TractResult<DeclaredMetadata> {
+    let dimensions = |fact: &TypedFact| {
+        fact.shape
+            .as_concrete()
+            .map(|dims| dims.iter().map(|&d| d as u32).collect::<Vec<u32>>())
+    };
+    let input = model.input_fact(0)?;
+
+    Ok(DeclaredMetadata {
+        tensor_type: tensor_type(input.datum_type),
+        tensor_dimensions_in: dimensions(input),
+        tensor_dimensions_out: dimensions(model.output_fact(0)?),
+    })
+}
+
+/// The interface's name of a datum type, e.g. 'S8' for 'I8', None if it has none.
+/// Quantized types are named by their storage type.
+pub fn tensor_type(datum_type: DatumType) -> Option<String> {
+    let name = match datum_type.unquantized() {
+        DatumType::U8 => "U8",
+        DatumType::U16 => "U16",
+        DatumType::U32 => "U32",
+        DatumType::U64 => "U64",
+        DatumType::I8 => "S8",
+        DatumType::I16 => "S16",
+        DatumType::I32 => "S32",
+        DatumType::I64 => "S64",
+        DatumType::F16 => "F16",
+        DatumType::F32 => "F32",
+        DatumType::F64 => "F64",
+        _ => return None,
+    };
+
+    Some(name.to_string())
+}
+
+/// Edge TPU models need the custom op of the device and declare nothing here.
+#[cfg(any(feature = "tflite", feature = "edgetpu"))]
+fn introspect_tflite(model: &[u8]) -> Option<DeclaredMetadata> {
+    let model = FlatBufferModel::build_from_buffer(model.to_vec()).ok()?;
+    let interpreter = InterpreterBuilder::new(model, BuiltinOpResolver::default())
+        .ok()?
+        .build()
+        .ok()?;
+
+    let input = interpreter.tensor_info(*interpreter.inputs().first()?)?;
+    let output = interpreter.tensor_info(*interpreter.outputs().first()?)?;
+    let tensor_type = match input.element_kind {
+        tflite::context::ElementKind::kTfLiteFloat32 => Some("F32".to_string()),
+        tflite::context::ElementKind::kTfLiteUInt8 => Some("U8".to_string()),
+        tflite::context::ElementKind::kTfLiteInt8 => Some("S8".to_string()),
+        _ => None,
+    };
+
+    Some(DeclaredMetadata {
+        tensor_type,
+        tensor_dimensions_in: Some(input.dims.iter().map(|&d| d as u32).collect()),
+        tensor_dimensions_out: Some(output.dims.iter().map(|&d| d as u32).collect()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoding_is_detected() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let tflite = std::fs::read("../../bindle/models/mobilenetv1_uint8_quant.tflite").unwrap();
+        let pb = std::fs::read("../../bindle/models/plus3.pb").unwrap();
+
+        assert_eq!(detect_encoding(&onnx), Some(GraphEncoding::Onnx));
+        assert_eq!(detect_encoding(&tflite), Some(GraphEncoding::TfLite));
+        assert_eq!(detect_encoding(&pb), Some(GraphEncoding::Tensorflow));
+    }
+
+    #[test]
+    fn edge_tpu_models_are_detected() {
+        let tflite = std::fs::read("../../bindle/models/mobilenetv1_uint8_quant.tflite").unwrap();
+
+        assert!(!requires_edge_tpu(&tflite));
+        assert!(requires_edge_tpu(b"TFL3...edgetpu-custom-op..."));
+    }
+
+    #[test]
+    fn missing_metadata_is_inferred() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let metadata = complete_metadata("identity", None, &onnx).unwrap();
+
+        assert_eq!(metadata.graph_encoding, GraphEncoding::Onnx);
+        assert_eq!(metadata.tensor_type, "F32");
+        assert_eq!(metadata.tensor_dimensions_in, Some(vec![1, 4]));
+        assert_eq!(metadata.tensor_dimensions_out, Some(vec![1, 4]));
+    }
+
+    #[test]
+    fn shipped_metadata_takes_precedence() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let shipped = br#"{"tensor_type":"F32","tensor_dimensions_in":[1, 2, 3]}"#;
+        let metadata = complete_metadata("identity", Some(shipped), &onnx).unwrap();
+
+        assert_eq!(metadata.tensor_dimensions_in, Some(vec![1, 2, 3]));
+        assert_eq!(metadata.tensor_dimensions_out, Some(vec![1, 4]));
+    }
+
+    #[test]
+    fn tensor_types_are_those_of_the_interface() {
+        use wasmcloud_interface_mlinference::ValueType;
+
+        let value_type = |datum_type: DatumType| {
+            ValueType::try_from(tensor_type(datum_type).unwrap().as_str()).unwrap()
+        };
+
+        assert_eq!(value_type(DatumType::U8), ValueType::ValueU8);
+        assert_eq!(value_type(DatumType::I8), ValueType::ValueS8);
+        assert_eq!(value_type(DatumType::I32), ValueType::ValueS32);
+        assert_eq!(value_type(DatumType::I64), ValueType::ValueS64);
+        assert_eq!(value_type(DatumType::F16), ValueType::ValueF16);
+        assert_eq!(value_type(DatumType::F32), ValueType::ValueF32);
+        assert_eq!(
+            value_type(DatumType::QI8(QParams::ZpScale {
+                zero_point: 0,
+                scale: 0.5
+            })),
+            ValueType::ValueS8
+        );
+        assert_eq!(tensor_type(DatumType::Bool), None);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::{BindleLoader, ExecutionTarget, GraphEncoding};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::Path};
+
+/// group holding the model, see `BindleLoader::get_model_and_metadata`
+pub const MODEL_GROUP: &str = "model";
+
+/// optional group holding the metadata, see `BindleLoader::get_model_and_metadata`
+pub const METADATA_GROUP: &str = "metadata";
+
+/// optional group holding the labels of the model's output classes, one per line
+pub const LABELS_GROUP: &str = "labels";
+
+/// optional group holding a CPU variant of an Edge TPU model, see `BindleLoader::get_cpu_variant`
+pub const CPU_VARIANT_GROUP: &str = "model-cpu";
+
+/// label annotation of a model parcel naming its graph encoding, e.g. 'onnx'
+pub const ENCODING_ANNOTATION: &str = "encoding";
+
+/// label annotation of a model parcel naming its execution target, e.g. 'tpu'
+pub const TARGET_ANNOTATION: &str = "target";
+
+/// label annotation of a model parcel naming its precision, e.g. 'f32' or 'uint8'
+pub const PRECISION_ANNOTATION: &str = "precision";
+
+/// label annotation of a metadata parcel naming the model parcel it describes
+pub const MODEL_ANNOTATION: &str = "model";
+
+/// A file shipped as parcel of a model bindle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParcelFile {
+    pub name: String,
+    pub media_type: String,
+    pub sha256: String,
+    pub size: u64,
+    pub groups: Vec<String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl ParcelFile {
+    /// describes the given content as parcel
+    pub fn new(name: &str, media_type: &str, data: &[u8], groups: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            media_type: media_type.to_string(),
+            sha256: sha256_hex(data),
+            size: data.len() as u64,
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    /// adds a label annotation, e.g. the target of a model variant
+    pub fn with_annotation(mut self, key: &str, value: &str) -> Self {
+        self.annotations.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// describes the file at 'path' as parcel, named after the file
+    pub fn from_path(path: &Path, media_type: &str, groups: &[&str]) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Ok(ParcelFile::new(&name, media_type, &data, groups))
+    }
+
+    /// the parcel as listed by an invoice, e.g. for sources of models other than bindle
+    pub fn to_parcel(&self) -> bindle::Parcel {
+        let parcel = serde_json::json!({
+            "label": {
+                "sha256": self.sha256,
+                "mediaType": self.media_type,
+                "name": self.name,
+                "size": self.size,
+                "annotations": (!self.annotations.is_empty()).then_some(&self.annotations),
+            },
+            "conditions": { "memberOf": self.groups },
+        });
+
+        serde_json::from_value(parcel).expect("a parcel's fields are those of its label")
+    }
+}
+
+/// The bindle section of an invoice
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvoiceSpec {
+    pub name: String,
+    pub version: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+}
+
+/// Builds an invoice of the given parcels. Groups are declared in order of
+/// their first appearance and are all required.
+pub fn build_invoice(spec: &InvoiceSpec, parcels: &[ParcelFile]) -> bindle::Invoice {
+    let mut groups: Vec<&str> = Vec::new();
+    for group in parcels.iter().flat_map(|p| p.groups.iter()) {
+        if !groups.contains(&group.as_str()) {
+            groups.push(group);
+        }
+    }
+
+    let invoice = serde_json::json!({
+        "bindleVersion": "1.0.0",
+        "bindle": {
+            "name": spec.name,
+            "version": spec.version,
+            "authors": (!spec.authors.is_empty()).then_some(&spec.authors),
+            "description": spec.description,
+        },
+        "group": groups
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "satisfiedBy": "allOf", "required": true }))
+            .collect::<Vec<_>>(),
+        "parcel": parcels.iter().map(ParcelFile::to_parcel).collect::<Vec<_>>(),
+    });
+
+    serde_json::from_value(invoice).expect("an invoice's fields are those of its spec and parcels")
+}
+
+/// Renders the invoice of the given parcels as TOML, see `build_invoice`
+pub fn invoice_toml(
+    spec: &InvoiceSpec,
+    parcels: &[ParcelFile],
+) -> Result<String, toml::ser::Error> {
+    toml::to_string(&build_invoice(spec, parcels))
+}
+
+/// Invoice of a bindle 'plus3' holding 'data' as its only model parcel
+#[cfg(test)]
+pub(crate) fn single_model_invoice(data: &[u8]) -> bindle::Invoice {
+    let spec = InvoiceSpec {
+        name: "plus3".to_string(),
+        version: "0.1.0".to_string(),
+        ..Default::default()
+    };
+    let parcels = vec![ParcelFile::new(
+        "m.onnx",
+        "application/octet-stream",
+        data,
+        &[MODEL_GROUP],
+    )];
+
+    build_invoice(&spec, &parcels)
+}
+
+/// A variant of a model as described by the annotations of its parcel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelVariant {
+    pub encoding: Option<GraphEncoding>,
+    pub target: Option<ExecutionTarget>,
+    pub precision: Option<String>,
+}
+
+impl ModelVariant {
+    /// reads the variant's annotations, failing on unknown encodings or targets
+    pub fn of(parcel: &bindle::Parcel) -> Result<Self, String> {
+        let annotations = parcel.label.annotations.clone().unwrap_or_default();
+
+        Ok(ModelVariant {
+            encoding: annotation(&annotations, ENCODING_ANNOTATION)?,
+            target: annotation(&annotations, TARGET_ANNOTATION)?,
+            precision: annotations.get(PRECISION_ANNOTATION).cloned(),
+        })
+    }
+}
+
+/// Preferences selecting one of several variants of a model, see `select_variant`
+#[derive(Debug, Clone, Default)]
+pub struct VariantPreference {
+    /// targets in the order of preference, any if empty
+    pub targets: Vec<ExecutionTarget>,
+
+    /// precision preferred among the variants of the same target
+    pub precision: Option<String>,
+}
+
+/// Selects the parcel of group 'model' of the most preferred target, then of
+/// the preferred precision, then the first in the invoice. Variants whose
+/// encoding and target no engine 'supports' are skipped, parcels without
+/// annotations match any target at the lowest preference.
+pub fn select_variant<'a, F>(
+    parcels: &'a [bindle::Parcel],
+    preference: &VariantPreference,
+    supports: F,
+) -> Option<(&'a bindle::Parcel, ModelVariant)>
+where
+    F: Fn(&GraphEncoding, &ExecutionTarget) -> bool,
+{
+    let mut selected: Option<((usize, bool), &bindle::Parcel, ModelVariant)> = None;
+
+    for parcel in BindleLoader::get_members_of(parcels, MODEL_GROUP) {
+        let variant = match ModelVariant::of(parcel) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("skipping model parcel '{}': {}", parcel.label.name, e);
+                continue;
+            }
+        };
+
+        let target_rank = match (&variant.target, preference.targets.is_empty()) {
+            (_, true) => 0,
+            (Some(target), false) => match preference.targets.iter().position(|t| t == target) {
+                Some(position) => position,
+                None => continue,
+            },
+            (None, false) => preference.targets.len(),
+        };
+
+        let supported = match (&variant.encoding, &variant.target) {
+            (Some(encoding), Some(target)) => supports(encoding, target),
+            (Some(encoding), None) if !preference.targets.is_empty() => {
+                preference.targets.iter().any(|t| supports(encoding, t))
+            }
+            _ => true,
+        };
+        if !supported {
+            continue;
+        }
+
+        let rank = (
+            target_rank,
+            preference.precision.is_some() && variant.precision != preference.precision,
+        );
+        if selected.as_ref().is_none_or(|(r, _, _)| rank < *r) {
+            selected = Some((rank, parcel, variant));
+        }
+    }
+
+    selected.map(|(_, parcel, variant)| (parcel, variant))
+}
+
+/// parses an annotation by the serde names of its type, e.g. 'tf_lite' or 'tpu'
+fn annotation<T: serde::de::DeserializeOwned>(
+    annotations: &BTreeMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, String> {
+    annotations
+        .get(key)
+        .map(|value| {
+            serde_json::from_value(serde_json::Value::String(value.clone()))
+                .map_err(|_| format!("unknown {} '{}'", key, value))
+        })
+        .transpose()
+}
+
+/// Problems preventing the provider from loading the bindle, none if valid
+pub fn validate_invoice(invoice: &bindle::Invoice) -> Vec<String> {
+    let mut problems = Vec::new();
+    let parcels = invoice.parcel.clone().unwrap_or_default();
+    let groups: Vec<&str> = invoice
+        .group
+        .iter()
+        .flatten()
+        .map(|g| g.name.as_str())
+        .collect();
+
+    let models = BindleLoader::get_members_of(&parcels, MODEL_GROUP);
+    if models.is_empty() {
+        problems.push(format!("no parcel is member of group '{}'", MODEL_GROUP));
+    }
+
+    let mut variants: Vec<ModelVariant> = Vec::new();
+    for parcel in models.iter() {
+        match ModelVariant::of(parcel) {
+            Ok(variant) if variants.contains(&variant) => problems.push(format!(
+                "model parcel '{}' does not differ from another by its annotations",
+                parcel.label.name
+            )),
+            Ok(variant) => variants.push(variant),
+            Err(e) => problems.push(format!("model parcel '{}': {}", parcel.label.name, e)),
+        }
+    }
+
+    for parcel in parcels.iter() {
+        for group in parcel
+            .conditions
+            .iter()
+            .flat_map(|c| c.member_of.iter().flatten())
+        {
+            if !groups.contains(&group.as_str()) {
+                problems.push(format!(
+                    "parcel '{}' is member of undeclared group '{}'",
+                    parcel.label.name, group
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// sha256 of 'data' as lowercase hex, as declared by the labels of parcels
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parcel_is_hashed() {
+        let parcel = ParcelFile::new("a.onnx", "application/octet-stream", b"abc", &["model"]);
+
+        assert_eq!(
+            parcel.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(parcel.size, 3);
+    }
+
+    #[test]
+    fn invoice_can_be_parsed_by_bindle() {
+        let spec = InvoiceSpec {
+            name: "plus3".to_string(),
+            version: "0.1.0".to_string(),
+            authors: vec!["someone".to_string()],
+            description: Some("the 'plus3' \"model\"".to_string()),
+        };
+        let parcels = vec![
+            ParcelFile::new("m.onnx", "application/octet-stream", b"m", &[MODEL_GROUP]),
+            ParcelFile::new("m.json", "application/json", b"{}", &[METADATA_GROUP]),
+        ];
+
+        let invoice: bindle::Invoice =
+            toml::from_str(&invoice_toml(&spec, &parcels).unwrap()).unwrap();
+
+        assert_eq!(invoice.bindle.id.name(), "plus3");
+        assert_eq!(invoice.group.unwrap().len(), 2);
+        assert_eq!(invoice.bindle.description, spec.description);
+        assert_eq!(invoice.parcel.unwrap()[1].label.size, 2);
+    }
+
+    fn variants() -> Vec<bindle::Parcel> {
+        let spec = InvoiceSpec {
+            name: "mobilenet".to_string(),
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        let parcels = vec![
+            ParcelFile::new("m.onnx", "application/octet-stream", b"f", &[MODEL_GROUP])
+                .with_annotation(ENCODING_ANNOTATION, "onnx")
+                .with_annotation(TARGET_ANNOTATION, "cpu")
+                .with_annotation(PRECISION_ANNOTATION, "f32"),
+            ParcelFile::new("m.tflite", "application/octet-stream", b"u", &[MODEL_GROUP])
+                .with_annotation(ENCODING_ANNOTATION, "tf_lite")
+                .with_annotation(TARGET_ANNOTATION, "cpu")
+                .with_annotation(PRECISION_ANNOTATION, "uint8"),
+            ParcelFile::new(
+                "m_edgetpu.tflite",
+                "application/octet-stream",
+                b"e",
+                &[MODEL_GROUP],
+            )
+            .with_annotation(ENCODING_ANNOTATION, "tf_lite")
+            .with_annotation(TARGET_ANNOTATION, "tpu")
+            .with_annotation(PRECISION_ANNOTATION, "uint8"),
+        ];
+
+        let invoice = build_invoice(&spec, &parcels);
+        assert!(validate_invoice(&invoice).is_empty());
+        invoice.parcel.unwrap()
+    }
+
+    fn select(
+        parcels: &[bindle::Parcel],
+        targets: &[ExecutionTarget],
+        precision: Option<&str>,
+    ) -> String {
+        let preference = VariantPreference {
+            targets: targets.to_vec(),
+            precision: precision.map(str::to_string),
+        };
+        let (parcel, _) = select_variant(parcels, &preference, |_, _| true).unwrap();
+        parcel.label.name.clone()
+    }
+
+    #[test]
+    fn variant_of_preferred_target_is_selected() {
+        let parcels = variants();
+
+        assert_eq!(
+            select(
+                &parcels,
+                &[ExecutionTarget::Tpu, ExecutionTarget::Cpu],
+                None
+            ),
+            "m_edgetpu.tflite"
+        );
+        assert_eq!(select(&parcels, &[ExecutionTarget::Cpu], None), "m.onnx");
+        assert_eq!(
+            select(&parcels, &[ExecutionTarget::Cpu], Some("uint8")),
+            "m.tflite"
+        );
+        assert_eq!(select(&parcels, &[], Some("uint8")), "m.tflite");
+    }
+
+    #[test]
+    fn unsupported_variants_are_skipped() {
+        let parcels = variants();
+        let preference = VariantPreference {
+            targets: vec![ExecutionTarget::Tpu, ExecutionTarget::Cpu],
+            precision: None,
+        };
+
+        let (parcel, variant) = select_variant(&parcels, &preference, |encoding, target| {
+            *encoding == GraphEncoding::Onnx && *target == ExecutionTarget::Cpu
+        })
+        .unwrap();
+
+        assert_eq!(parcel.label.name, "m.onnx");
+        assert_eq!(variant.encoding, Some(GraphEncoding::Onnx));
+    }
+
+    #[test]
+    fn invoice_without_model_is_invalid() {
+        let spec = InvoiceSpec {
+            name: "plus3".to_string(),
+            version: "0.1.0".to_string(),
+            ..Default::default()
+        };
+        let parcels = vec![ParcelFile::new(
+            "m.json",
+            "application/json",
+            b"{}",
+            &[METADATA_GROUP],
+        )];
+
+        let invoice = build_invoice(&spec, &parcels);
+
+        assert_eq!(
+            validate_invoice(&invoice),
+            vec!["no parcel is member of group 'model'".to_string()]
+        );
+    }
+}
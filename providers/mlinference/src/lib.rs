@@ -1,23 +1,90 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 use thiserror::Error as ThisError;
 use wasmcloud_interface_mlinference::{InferenceOutput, MlError, Status, Tensor, ValueType};
 
+mod admission;
+pub use admission::{
+    AdmissionError, AdmissionSettings, ModelGate, ModelLimits, RateLimit, TokenBucket,
+};
+
+mod cache;
+pub use cache::{CacheKey, CacheSettings, InferenceCache};
+
 mod bindle_loader;
 pub use bindle_loader::{BindleLoader, ModelMetadata};
 
+mod download;
+pub use download::{DownloadSettings, Downloader};
+
+mod loader;
+pub use loader::{model_loader, BindleModelLoader, ModelLoader};
+
+mod memory;
+pub use memory::{
+    drop_evicted, InUse, Loaded, MemoryBudget, MemorySettings, Residency, ResidentKey,
+    MEMORY_ENV_PREFIX,
+};
+
+mod oci;
+pub use oci::{
+    OciError, OciModelLoader, OciReference, OciSettings, CPU_VARIANT_MEDIA_TYPE, LABELS_MEDIA_TYPE,
+    METADATA_MEDIA_TYPE, MODEL_MEDIA_TYPE, PROFILE_MEDIA_TYPE, WARMUP_MEDIA_TYPE,
+};
+
 pub mod inference;
 
+mod introspect;
+pub use introspect::{
+    complete_metadata, detect_encoding, introspect, requires_edge_tpu, tensor_type,
+    DeclaredMetadata,
+};
+
+mod invoice;
+pub use invoice::{
+    build_invoice, invoice_toml, select_variant, sha256_hex, validate_invoice, InvoiceSpec,
+    ModelVariant, ParcelFile, VariantPreference, CPU_VARIANT_GROUP, ENCODING_ANNOTATION,
+    LABELS_GROUP, METADATA_GROUP, MODEL_ANNOTATION, MODEL_GROUP, PRECISION_ANNOTATION,
+    TARGET_ANNOTATION,
+};
+
 #[cfg(any(feature = "tflite", feature = "edgetpu"))]
 pub use inference::TfLiteEngine;
 
+#[cfg(feature = "tract-tflite")]
+pub use inference::TractTfLiteEngine;
+
+#[cfg(feature = "candle")]
+pub use inference::{CandleArchitecture, CandleEngine};
+
 pub use inference::{
-    bytes_to_f32_vec, f32_array_to_bytes, ExecutionTarget, Graph, GraphEncoding,
-    GraphExecutionContext, InferenceEngine, TractEngine,
+    bytes_to_f32_vec, f32_array_to_bytes, Dimension, ExecutionTarget, Graph, GraphEncoding,
+    GraphExecutionContext, InferenceEngine, InferenceError, InferenceResult, TfLiteOptions,
+    TractEngine,
 };
 
+mod registry;
+pub use registry::{EngineRegistry, EngineSelectionError, InitializedModel};
+
+mod signature;
+pub use signature::{verify_parcel, InvoiceVerifier, SignatureSettings};
+
 mod settings;
-pub use settings::{load_settings, ModelSettings};
+pub use settings::{
+    load_settings, load_settings_file, model_id, split_reference, ModelOptions, ModelSettings,
+    ZooEntry, MODEL_SCHEMES,
+};
+
+mod monitor;
+pub use monitor::{
+    ChannelStatistics, DriftWarning, InputMonitor, InputProfile, InputStatistics, MonitorSettings,
+};
+
+mod recorder;
+pub use recorder::{read_recording, Record, Recorder, RecorderSettings, TensorDiff};
+
+mod warmup;
+pub use warmup::{run_warmup, warmup_tensors, WarmupError, WarmupFill, WarmupSettings};
 
 mod hashmap_ci;
 pub(crate) use hashmap_ci::make_case_insensitive;
@@ -27,10 +94,15 @@ pub type ModelName = String;
 pub type ModelZoo = HashMap<ModelName, ModelContext>;
 pub type Engine = Arc<Box<dyn InferenceEngine + Send + Sync>>;
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash)]
+/// Framework of an engine, to be preferred by a model in its metadata or by a link in its settings
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum InferenceFramework {
     Tract,
     TfLite,
+    /// TfLite models run by tract's tflite frontend
+    TractTfLite,
+    Candle,
 }
 
 impl Default for InferenceFramework {
@@ -44,9 +116,30 @@ pub struct ModelContext {
     pub bindle_url: BindlePath,
     pub graph_encoding: GraphEncoding,
     pub execution_target: ExecutionTarget,
+    pub framework: InferenceFramework,
     pub value_type: ValueType,
     pub graph_execution_context: GraphExecutionContext,
     pub graph: Graph,
+    pub tensor_dimensions_in: Option<Vec<u32>>,
+    pub tensor_dimensions_out: Option<Vec<u32>>,
+    pub non_deterministic: bool,
+    /// labels of the output classes, if shipped with the model
+    #[serde(skip)]
+    pub labels: Option<Arc<Vec<String>>>,
+    /// creator of the model's invoice as verified by its signature
+    pub signer: Option<String>,
+    /// time after which an inference is answered by an error
+    #[serde(skip)]
+    pub timeout: Option<std::time::Duration>,
+    #[serde(skip)]
+    pub gate: Arc<ModelGate>,
+    #[serde(skip)]
+    pub cache: Option<Arc<InferenceCache>>,
+    #[serde(skip)]
+    pub monitor: Option<Arc<InputMonitor>>,
+    /// whether the model is loaded or evicted by the memory budget
+    #[serde(skip)]
+    pub residency: Arc<Residency>,
 }
 
 impl ModelContext {
@@ -55,9 +148,20 @@ impl ModelContext {
             bindle_url: Default::default(),
             graph_encoding: Default::default(),
             execution_target: Default::default(),
+            framework: Default::default(),
             value_type: ValueType::ValueF32,
             graph_execution_context: Default::default(),
             graph: Default::default(),
+            tensor_dimensions_in: None,
+            tensor_dimensions_out: None,
+            non_deterministic: false,
+            labels: None,
+            signer: None,
+            timeout: None,
+            gate: Default::default(),
+            cache: None,
+            monitor: None,
+            residency: Default::default(),
         }
     }
 
@@ -67,6 +171,11 @@ impl ModelContext {
         self.value_type =
             ValueType::try_from(metadata.tensor_type.as_str()).map_err(MlError::InvalidModel)?;
         self.execution_target = metadata.execution_target;
+        self.tensor_dimensions_in = metadata.tensor_dimensions_in;
+        self.tensor_dimensions_out = metadata.tensor_dimensions_out;
+        self.non_deterministic = metadata.non_deterministic;
+        self.labels = metadata.labels.map(Arc::new);
+        self.signer = metadata.signer;
 
         Ok(self)
     }
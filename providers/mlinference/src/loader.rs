@@ -0,0 +1,67 @@
+//! Sources of models, selected by the scheme of a model's reference
+//!
+//! A source lists the parcels of a model, the parcels of a bindle's invoice
+//! or the layers of an OCI manifest, and fetches their content. Which parcel
+//! is the model, its metadata, labels and so on is decided by their groups
+//! and annotations the same way for all sources, see `BindleLoader`.
+use crate::{
+    bindle_loader::BindleResult, BindleLoader, Downloader, InvoiceVerifier, ModelSettings,
+    OciModelLoader,
+};
+use async_trait::async_trait;
+
+/// Lists and fetches the parcels of models
+#[async_trait]
+pub trait ModelLoader: Send + Sync {
+    /// parcels of the model 'id' and the creator of its artifact as verified by its signature
+    async fn get_parcels(&self, id: &str) -> BindleResult<(Vec<bindle::Parcel>, Option<String>)>;
+
+    /// content of a parcel of the model 'id', verified against its sha256
+    async fn get_parcel(&self, id: &str, parcel: &bindle::Parcel) -> BindleResult<Vec<u8>>;
+}
+
+/// Loads models from the bindle server, verifying their invoices
+pub struct BindleModelLoader {
+    downloader: Downloader,
+    verifier: InvoiceVerifier,
+}
+
+impl BindleModelLoader {
+    pub fn new(downloader: Downloader, verifier: InvoiceVerifier) -> Self {
+        BindleModelLoader {
+            downloader,
+            verifier,
+        }
+    }
+}
+
+#[async_trait]
+impl ModelLoader for BindleModelLoader {
+    async fn get_parcels(&self, id: &str) -> BindleResult<(Vec<bindle::Parcel>, Option<String>)> {
+        let invoice = self.downloader.get_invoice(id).await?;
+        let signer = self.verifier.verify(&invoice)?;
+
+        Ok((invoice.parcel.unwrap_or_default(), signer))
+    }
+
+    async fn get_parcel(&self, id: &str, parcel: &bindle::Parcel) -> BindleResult<Vec<u8>> {
+        self.downloader.get_parcel(id, parcel).await
+    }
+}
+
+/// loader of the models referenced by 'scheme', one of `MODEL_SCHEMES`
+pub fn model_loader(scheme: &str, settings: &ModelSettings) -> BindleResult<Box<dyn ModelLoader>> {
+    match scheme {
+        "oci" => Ok(Box::new(OciModelLoader::new(
+            settings.oci.clone(),
+            settings.downloads.clone(),
+            settings.signatures.allow_unsigned,
+        ))),
+        _ => {
+            let downloader = BindleLoader::downloader("BINDLE_URL", settings.downloads.clone())?;
+            let verifier = InvoiceVerifier::from_settings(&settings.signatures)?;
+
+            Ok(Box::new(BindleModelLoader::new(downloader, verifier)))
+        }
+    }
+}
@@ -0,0 +1,433 @@
+//! Memory budget of the loaded models
+//!
+//! Each loaded model is accounted by the size of its model (and CPU variant)
+//! parcels, an estimate of the memory its graph and session take. Once a new
+//! model exceeds the provider-wide budget, the least recently used models
+//! not running an inference are evicted. An evicted model stays linked and
+//! is loaded again on its next inference, from `downloads.cache_dir` if set.
+use crate::{Engine, Error, Graph, GraphExecutionContext};
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+
+/// prefix of the environment variables of the memory budget, which are
+/// provider-wide rather than settings of a link
+pub const MEMORY_ENV_PREFIX: &str = "MLINFERENCE_MEMORY__";
+
+/// Settings of the memory budget of the loaded models
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemorySettings {
+    /// bytes of all loaded models of the provider, unbounded if not set
+    pub budget_bytes: Option<u64>,
+}
+
+impl MemorySettings {
+    /// settings of the provider's environment, e.g. 'MLINFERENCE_MEMORY__BUDGET_BYTES=536870912'
+    pub fn from_env(env: impl IntoIterator<Item = (String, String)>) -> Result<Self, Error> {
+        let mut settings = MemorySettings::default();
+
+        for (key, value) in env.into_iter() {
+            match key.strip_prefix(MEMORY_ENV_PREFIX) {
+                Some("BUDGET_BYTES") => {
+                    let budget = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|b| *b > 0)
+                        .ok_or_else(|| {
+                            Error::Settings(format!(
+                                "'{}' must be a positive number of bytes, not '{}'",
+                                key, value
+                            ))
+                        })?;
+                    settings.budget_bytes = Some(budget);
+                }
+                Some(_) => return Err(Error::Settings(format!("unknown setting '{}'", key))),
+                None => (),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// actor and name of a linked model
+pub type ResidentKey = (String, String);
+
+/// A model loaded by an engine
+#[derive(Clone)]
+pub struct Loaded {
+    pub engine: Engine,
+    pub graph: Graph,
+    pub graph_execution_context: GraphExecutionContext,
+    /// accounted size of the model
+    pub bytes: u64,
+}
+
+impl std::fmt::Debug for Loaded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Loaded")
+            .field("graph", &self.graph)
+            .field("graph_execution_context", &self.graph_execution_context)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct ResidencyState {
+    loaded: Option<Loaded>,
+    in_use: usize,
+    /// unlinked or replaced by a new link, unloaded by its last user
+    retired: bool,
+}
+
+/// Whether a linked model is loaded and how many inferences are using it
+#[derive(Default)]
+pub struct Residency {
+    state: Mutex<ResidencyState>,
+}
+
+impl Residency {
+    /// marks the model as in use until the returned guard is dropped,
+    /// which prevents its eviction, whether it is loaded or not
+    pub fn acquire(self: &Arc<Self>) -> InUse {
+        self.state.lock().unwrap().in_use += 1;
+
+        InUse {
+            residency: self.clone(),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.state.lock().unwrap().loaded.is_some()
+    }
+
+    /// Unloads the model once its link is deleted or replaced. The model is
+    /// returned if it is not in use, otherwise the last `InUse` guard drops it.
+    pub fn retire(&self) -> Option<Loaded> {
+        self.state.lock().unwrap().retired = true;
+        self.evict()
+    }
+
+    /// unloads the model unless it is in use
+    fn evict(&self) -> Option<Loaded> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.in_use {
+            0 => state.loaded.take(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Residency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+
+        f.debug_struct("Residency")
+            .field("loaded", &state.loaded)
+            .field("in_use", &state.in_use)
+            .field("retired", &state.retired)
+            .finish()
+    }
+}
+
+impl PartialEq for Residency {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Guard of a model in use, see `Residency::acquire`
+pub struct InUse {
+    residency: Arc<Residency>,
+}
+
+impl InUse {
+    /// the loaded model, None if it is evicted
+    pub fn loaded(&self) -> Option<Loaded> {
+        self.residency.state.lock().unwrap().loaded.clone()
+    }
+}
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        let mut state = self.residency.state.lock().unwrap();
+        state.in_use -= 1;
+
+        if state.in_use > 0 || !state.retired {
+            return;
+        }
+        if let Some(loaded) = state.loaded.take() {
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => {
+                    runtime.spawn(async move {
+                        loaded
+                            .engine
+                            .drop_model_state(&loaded.graph, &loaded.graph_execution_context)
+                            .await;
+                    });
+                }
+                Err(_) => log::warn!(
+                    "drop() - no runtime to drop the state of graph {}",
+                    loaded.graph
+                ),
+            }
+        }
+    }
+}
+
+/// The loaded models of all links, least recently used first to be evicted
+pub struct MemoryBudget {
+    budget: Option<u64>,
+    resident: Mutex<LruCache<ResidentKey, (Arc<Residency>, u64)>>,
+}
+
+impl MemoryBudget {
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget,
+            resident: Mutex::new(LruCache::unbounded()),
+        }
+    }
+
+    /// bytes of all loaded models
+    pub fn used(&self) -> u64 {
+        self.resident
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, (_, b))| b)
+            .sum()
+    }
+
+    /// marks a model as most recently used
+    pub fn touch(&self, key: &ResidentKey) {
+        self.resident.lock().unwrap().get(key);
+    }
+
+    /// stops accounting the model of 'residency' once it is unloaded,
+    /// unless the model of a newer link took its place
+    pub fn release(&self, key: &ResidentKey, residency: &Arc<Residency>) {
+        let mut resident = self.resident.lock().unwrap();
+
+        if matches!(resident.peek(key), Some((r, _)) if Arc::ptr_eq(r, residency)) {
+            resident.pop(key);
+        }
+    }
+
+    /// Records 'loaded' as the model of 'residency' and evicts the least
+    /// recently used models until all fit the budget. Returns the evicted
+    /// models, their states are to be dropped by `drop_evicted`.
+    pub fn admit(
+        &self,
+        key: ResidentKey,
+        residency: &Arc<Residency>,
+        loaded: Loaded,
+    ) -> Vec<(ResidentKey, Loaded)> {
+        let mut evicted = Vec::new();
+        let mut resident = self.resident.lock().unwrap();
+
+        let bytes = loaded.bytes;
+        {
+            let mut state = residency.state.lock().unwrap();
+            // loaded again by a link that is still in place
+            state.retired = false;
+            if let Some(previous) = state.loaded.replace(loaded) {
+                evicted.push((key.clone(), previous));
+            }
+        }
+
+        // the model of a link that has been replaced
+        if let Some((previous, _)) = resident.put(key.clone(), (residency.clone(), bytes)) {
+            if !Arc::ptr_eq(&previous, residency) {
+                evicted.extend(previous.retire().map(|l| (key.clone(), l)));
+            }
+        }
+
+        let budget = match self.budget {
+            Some(b) => b,
+            None => return evicted,
+        };
+
+        // least recently used first
+        let candidates: Vec<ResidentKey> = resident
+            .iter()
+            .map(|(k, _)| k.clone())
+            .filter(|k| *k != key)
+            .collect();
+        let mut used: u64 = resident.iter().map(|(_, (_, b))| b).sum();
+
+        for candidate in candidates.into_iter().rev() {
+            if used <= budget {
+                break;
+            }
+
+            let (candidate_residency, candidate_bytes) = match resident.peek(&candidate) {
+                Some((r, b)) => (r.clone(), *b),
+                None => continue,
+            };
+            if let Some(l) = candidate_residency.evict() {
+                resident.pop(&candidate);
+                used -= candidate_bytes;
+                evicted.push((candidate, l));
+            }
+        }
+
+        if used > budget {
+            log::warn!(
+                "admit() - {} bytes of loaded models exceed the budget of {} bytes, the others are in use",
+                used,
+                budget
+            );
+        }
+
+        evicted
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget::new(None)
+    }
+}
+
+/// drops the states of evicted models
+pub async fn drop_evicted(evicted: Vec<(ResidentKey, Loaded)>) {
+    for ((actor, name), loaded) in evicted {
+        log::info!(
+            "drop_evicted() - evicting model '{}' of actor {} ({} bytes)",
+            name,
+            actor,
+            loaded.bytes
+        );
+
+        loaded
+            .engine
+            .drop_model_state(&loaded.graph, &loaded.graph_execution_context)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TractEngine;
+
+    fn loaded(bytes: u64) -> Loaded {
+        Loaded {
+            engine: Arc::new(Box::new(TractEngine::default())),
+            graph: 0,
+            graph_execution_context: 0,
+            bytes,
+        }
+    }
+
+    fn key(name: &str) -> ResidentKey {
+        ("actor".to_string(), name.to_string())
+    }
+
+    #[test]
+    fn least_recently_used_models_are_evicted() {
+        let budget = MemoryBudget::new(Some(250));
+        let (a, b, c) = (
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+        );
+
+        assert!(budget.admit(key("a"), &a, loaded(100)).is_empty());
+        assert!(budget.admit(key("b"), &b, loaded(100)).is_empty());
+        budget.touch(&key("a"));
+
+        let evicted = budget.admit(key("c"), &c, loaded(100));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, key("b"));
+        assert!(a.is_loaded() && !b.is_loaded() && c.is_loaded());
+        assert_eq!(budget.used(), 200);
+    }
+
+    #[test]
+    fn models_in_use_are_not_evicted() {
+        let budget = MemoryBudget::new(Some(150));
+        let (a, b, c) = (
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+        );
+
+        budget.admit(key("a"), &a, loaded(100));
+        let in_use = a.acquire();
+
+        assert!(budget.admit(key("b"), &b, loaded(100)).is_empty());
+        assert!(in_use.loaded().is_some());
+        assert_eq!(budget.used(), 200);
+
+        drop(in_use);
+        let evicted: Vec<ResidentKey> = budget
+            .admit(key("c"), &c, loaded(100))
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(evicted, vec![key("a"), key("b")]);
+        assert_eq!(budget.used(), 100);
+    }
+
+    #[tokio::test]
+    async fn replaced_models_are_dropped_by_their_last_user() {
+        let budget = MemoryBudget::new(None);
+        let (old, new) = (
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+        );
+
+        budget.admit(key("a"), &old, loaded(100));
+        let in_use = old.acquire();
+
+        // a new link of the same model while an inference of the old one runs
+        assert!(budget.admit(key("a"), &new, loaded(100)).is_empty());
+        assert!(in_use.loaded().is_some());
+
+        drop(in_use);
+        assert!(!old.is_loaded());
+        assert!(new.is_loaded());
+        assert_eq!(budget.used(), 100);
+    }
+
+    #[test]
+    fn releasing_a_replaced_model_keeps_its_successor() {
+        let budget = MemoryBudget::new(None);
+        let (old, new) = (
+            Arc::new(Residency::default()),
+            Arc::new(Residency::default()),
+        );
+
+        budget.admit(key("a"), &old, loaded(100));
+        budget.admit(key("a"), &new, loaded(50));
+
+        budget.release(&key("a"), &old);
+        assert_eq!(budget.used(), 50);
+        budget.release(&key("a"), &new);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn budget_is_read_from_the_environment() {
+        let env = |value: &str| {
+            vec![
+                (
+                    "MLINFERENCE_MEMORY__BUDGET_BYTES".to_string(),
+                    value.to_string(),
+                ),
+                ("MLINFERENCE_CACHE__CAPACITY".to_string(), "16".to_string()),
+            ]
+        };
+
+        assert_eq!(
+            MemorySettings::from_env(env("1024")).unwrap().budget_bytes,
+            Some(1024)
+        );
+        assert!(MemorySettings::from_env(env("0")).is_err());
+        assert!(MemorySettings::from_env(env("1 GiB")).is_err());
+        assert_eq!(MemorySettings::from_env(vec![]).unwrap().budget_bytes, None);
+    }
+}
@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use wasmcloud_interface_mlinference::{Tensor, ValueType};
+
+/// Settings of input drift and data-quality monitoring
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MonitorSettings {
+    /// maintain input statistics and check them against the model's profile
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// number of requests aggregated before statistics are checked
+    #[serde(default = "MonitorSettings::default_window")]
+    pub window: u64,
+
+    /// tolerated deviation of a channel mean, in units of the reference std
+    #[serde(default = "MonitorSettings::default_mean_threshold")]
+    pub mean_threshold: f64,
+
+    /// tolerated ratio between observed and reference std (and its inverse)
+    #[serde(default = "MonitorSettings::default_std_ratio_threshold")]
+    pub std_ratio_threshold: f64,
+}
+
+impl MonitorSettings {
+    fn default_window() -> u64 {
+        100
+    }
+
+    fn default_mean_threshold() -> f64 {
+        3.0
+    }
+
+    fn default_std_ratio_threshold() -> f64 {
+        2.0
+    }
+}
+
+impl Default for MonitorSettings {
+    fn default() -> Self {
+        MonitorSettings {
+            enabled: false,
+            window: MonitorSettings::default_window(),
+            mean_threshold: MonitorSettings::default_mean_threshold(),
+            std_ratio_threshold: MonitorSettings::default_std_ratio_threshold(),
+        }
+    }
+}
+
+impl Eq for MonitorSettings {}
+
+/// Reference distribution of a model's input, shipped as parcel of group 'profile'
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InputProfile {
+    /// axis of the input tensor holding the channels, e.g. 1 for NCHW, 3 for NHWC
+    #[serde(default = "InputProfile::default_channel_axis")]
+    pub channel_axis: usize,
+
+    /// expected mean per channel
+    pub mean: Vec<f64>,
+
+    /// expected standard deviation per channel
+    pub std: Vec<f64>,
+
+    /// lowest expected value (optional)
+    #[serde(default)]
+    pub min: Option<f64>,
+
+    /// highest expected value (optional)
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl InputProfile {
+    fn default_channel_axis() -> usize {
+        1
+    }
+
+    /// load profile from json
+    pub fn from_json(data: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(data)
+    }
+}
+
+/// Running mean and variance (Welford) of a channel
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ChannelStatistics {
+    pub count: u64,
+    pub mean: f64,
+    #[serde(skip)]
+    m2: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ChannelStatistics {
+    fn push(&mut self, x: f64) {
+        if self.count == 0 {
+            self.min = x;
+            self.max = x;
+        }
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+
+    pub fn std(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            (self.m2 / (self.count - 1) as f64).sqrt()
+        }
+    }
+}
+
+/// Statistics of a model's inputs
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct InputStatistics {
+    /// per-channel statistics of the current window
+    pub channels: Vec<ChannelStatistics>,
+    /// requests observed in the current window
+    pub window_requests: u64,
+    /// requests observed since linkage
+    pub requests: u64,
+    pub nan_count: u64,
+    pub inf_count: u64,
+    /// number of requests per input shape
+    pub shapes: HashMap<String, u64>,
+}
+
+/// A detected deviation from the reference profile
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriftWarning {
+    Mean {
+        channel: usize,
+        observed: f64,
+        expected: f64,
+    },
+    Std {
+        channel: usize,
+        observed: f64,
+        expected: f64,
+    },
+    Range {
+        observed_min: f64,
+        observed_max: f64,
+    },
+    Channels {
+        observed: usize,
+        expected: usize,
+    },
+    NonFinite {
+        nan_count: u64,
+        inf_count: u64,
+    },
+}
+
+impl std::fmt::Display for DriftWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriftWarning::Mean {
+                channel,
+                observed,
+                expected,
+            } => write!(
+                f,
+                "mean of channel {} is {:.4}, expected {:.4}",
+                channel, observed, expected
+            ),
+            DriftWarning::Std {
+                channel,
+                observed,
+                expected,
+            } => write!(
+                f,
+                "std of channel {} is {:.4}, expected {:.4}",
+                channel, observed, expected
+            ),
+            DriftWarning::Range {
+                observed_min,
+                observed_max,
+            } => write!(
+                f,
+                "values range from {:.4} to {:.4}, outside of the profile's range",
+                observed_min, observed_max
+            ),
+            DriftWarning::Channels { observed, expected } => write!(
+                f,
+                "input has {} channels, profile has {}",
+                observed, expected
+            ),
+            DriftWarning::NonFinite {
+                nan_count,
+                inf_count,
+            } => write!(
+                f,
+                "input contained {} NaN and {} infinite values",
+                nan_count, inf_count
+            ),
+        }
+    }
+}
+
+/// Maintains input statistics of a model and checks them against its profile
+#[derive(Debug)]
+pub struct InputMonitor {
+    model: String,
+    settings: MonitorSettings,
+    profile: Option<InputProfile>,
+    statistics: Mutex<InputStatistics>,
+    warnings: AtomicU64,
+}
+
+impl InputMonitor {
+    pub fn new(model: &str, settings: MonitorSettings, profile: Option<InputProfile>) -> Self {
+        Self {
+            model: model.to_string(),
+            settings,
+            profile,
+            statistics: Mutex::new(InputStatistics::default()),
+            warnings: AtomicU64::new(0),
+        }
+    }
+
+    /// number of warnings raised since linkage
+    pub fn warnings(&self) -> u64 {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    pub fn statistics(&self) -> InputStatistics {
+        self.statistics.lock().unwrap().clone()
+    }
+
+    /// Adds a request's input to the statistics. At the end of each window
+    /// the statistics are checked and the detected deviations are returned.
+    pub fn observe(&self, tensor: &Tensor) -> Vec<DriftWarning> {
+        let values: Vec<f64> = match tensor.value_types.first() {
+            Some(ValueType::ValueF32) => tensor
+                .data
+                .chunks_exact(4)
+                .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f64)
+                .collect(),
+            Some(ValueType::ValueU8) => tensor.data.iter().map(|&b| b as f64).collect(),
+            _ => return vec![],
+        };
+
+        let axis = self
+            .profile
+            .as_ref()
+            .map(|p| p.channel_axis)
+            .unwrap_or_else(InputProfile::default_channel_axis);
+        let dims: Vec<usize> = tensor.dimensions.iter().map(|&d| d as usize).collect();
+        let (channels, inner) = match dims.get(axis) {
+            Some(&c) if c > 0 => (c, dims[axis + 1..].iter().product::<usize>().max(1)),
+            _ => (1, values.len().max(1)),
+        };
+
+        let mut statistics = self.statistics.lock().unwrap();
+        if statistics.channels.len() != channels {
+            statistics.channels = vec![ChannelStatistics::default(); channels];
+        }
+
+        let (mut nan_count, mut inf_count) = (0, 0);
+        for (i, &x) in values.iter().enumerate() {
+            if x.is_nan() {
+                nan_count += 1;
+            } else if x.is_infinite() {
+                inf_count += 1;
+            } else {
+                statistics.channels[(i / inner) % channels].push(x);
+            }
+        }
+
+        statistics.nan_count += nan_count;
+        statistics.inf_count += inf_count;
+        statistics.requests += 1;
+        statistics.window_requests += 1;
+        *statistics
+            .shapes
+            .entry(format!("{:?}", tensor.dimensions))
+            .or_insert(0) += 1;
+
+        let mut warnings = Vec::new();
+        if nan_count > 0 || inf_count > 0 {
+            warnings.push(DriftWarning::NonFinite {
+                nan_count,
+                inf_count,
+            });
+        }
+
+        if statistics.window_requests >= self.settings.window {
+            if let Some(profile) = &self.profile {
+                warnings.extend(self.check(&statistics.channels, profile));
+            }
+            statistics.window_requests = 0;
+            statistics.channels = vec![ChannelStatistics::default(); channels];
+        }
+
+        if !warnings.is_empty() {
+            self.warnings
+                .fetch_add(warnings.len() as u64, Ordering::Relaxed);
+            for w in warnings.iter() {
+                log::warn!("input drift of model '{}': {}", self.model, w);
+            }
+        }
+        warnings
+    }
+
+    fn check(&self, channels: &[ChannelStatistics], profile: &InputProfile) -> Vec<DriftWarning> {
+        let mut warnings = Vec::new();
+
+        if channels.len() != profile.mean.len() || channels.len() != profile.std.len() {
+            warnings.push(DriftWarning::Channels {
+                observed: channels.len(),
+                expected: profile.mean.len(),
+            });
+            return warnings;
+        }
+
+        for (channel, stats) in channels.iter().enumerate() {
+            let (mean, std) = (profile.mean[channel], profile.std[channel]);
+
+            if (stats.mean - mean).abs() > self.settings.mean_threshold * std {
+                warnings.push(DriftWarning::Mean {
+                    channel,
+                    observed: stats.mean,
+                    expected: mean,
+                });
+            }
+
+            let ratio = stats.std() / std;
+            if std > 0.0
+                && (ratio > self.settings.std_ratio_threshold
+                    || ratio * self.settings.std_ratio_threshold < 1.0)
+            {
+                warnings.push(DriftWarning::Std {
+                    channel,
+                    observed: stats.std(),
+                    expected: std,
+                });
+            }
+        }
+
+        let observed_min = channels.iter().map(|c| c.min).fold(f64::INFINITY, f64::min);
+        let observed_max = channels
+            .iter()
+            .map(|c| c.max)
+            .fold(f64::NEG_INFINITY, f64::max);
+        if profile.min.is_some_and(|min| observed_min < min)
+            || profile.max.is_some_and(|max| observed_max > max)
+        {
+            warnings.push(DriftWarning::Range {
+                observed_min,
+                observed_max,
+            });
+        }
+
+        warnings
+    }
+}
+
+impl PartialEq for InputMonitor {
+    fn eq(&self, other: &Self) -> bool {
+        self.model == other.model
+            && self.settings == other.settings
+            && self.profile == other.profile
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tensor(values: &[f32]) -> Tensor {
+        Tensor {
+            value_types: vec![ValueType::ValueF32],
+            dimensions: vec![1, 2, values.len() as u32 / 2],
+            flags: 0,
+            data: values.iter().flat_map(|f| f.to_le_bytes()).collect(),
+        }
+    }
+
+    fn monitor() -> InputMonitor {
+        InputMonitor::new(
+            "m",
+            MonitorSettings {
+                enabled: true,
+                window: 1,
+                ..Default::default()
+            },
+            Some(InputProfile {
+                channel_axis: 1,
+                mean: vec![0.0, 0.0],
+                std: vec![1.0, 1.0],
+                min: Some(-5.0),
+                max: Some(5.0),
+            }),
+        )
+    }
+
+    #[test]
+    fn normalized_input_passes() {
+        let monitor = monitor();
+        assert_eq!(monitor.observe(&tensor(&[-1.0, 1.0, -1.0, 1.0])), vec![]);
+        assert_eq!(monitor.warnings(), 0);
+    }
+
+    #[test]
+    fn unnormalized_input_is_detected() {
+        let monitor = monitor();
+        let warnings = monitor.observe(&tensor(&[120.0, 130.0, 250.0, 255.0]));
+
+        assert!(warnings
+            .iter()
+            .any(|w| matches!(w, DriftWarning::Mean { channel: 1, .. })));
+        assert!(warnings
+            .iter()
+            .any(|w| matches!(w, DriftWarning::Range { .. })));
+        assert_eq!(monitor.statistics().requests, 1);
+    }
+
+    #[test]
+    fn non_finite_values_are_counted() {
+        let monitor = monitor();
+        let warnings = monitor.observe(&tensor(&[f32::NAN, 0.0, f32::INFINITY, 0.0]));
+
+        assert!(warnings.contains(&DriftWarning::NonFinite {
+            nan_count: 1,
+            inf_count: 1
+        }));
+        assert_eq!(monitor.statistics().shapes.get("[1, 2, 2]"), Some(&1));
+    }
+}
@@ -0,0 +1,432 @@
+//! Models stored in an OCI registry
+//!
+//! A model is an OCI artifact, e.g. `oci://localhost:5000/models/mobilenetv2-7:0.1.0`,
+//! whose layers are told apart by their media type: the model, its metadata,
+//! labels, warm-up samples, input profile and CPU variant, each taking the place
+//! of the bindle group of the same name. Layers carry the annotations of the
+//! parcels of a bindle, e.g. the target of a model variant, and are named by the
+//! standard annotation `org.opencontainers.image.title`. Layers of other media
+//! types are ignored. Manifests and blobs are fetched by the distribution API
+//! with the retries, timeouts and cache of the link's downloads.
+use crate::{
+    bindle_loader::BindleResult, sha256_hex, DownloadSettings, Downloader, ModelLoader, ParcelFile,
+    CPU_VARIANT_GROUP, LABELS_GROUP, METADATA_GROUP, MODEL_GROUP,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error as ThisError;
+
+/// media type of the layer holding the model
+pub const MODEL_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.model.v1";
+
+/// media type of the layer holding the CPU variant of an Edge TPU model
+pub const CPU_VARIANT_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.model-cpu.v1";
+
+/// media type of the layer holding the model's metadata
+pub const METADATA_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.metadata.v1+json";
+
+/// media type of the layer holding the labels of the model's output classes
+pub const LABELS_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.labels.v1";
+
+/// media type of the layers holding warm-up samples
+pub const WARMUP_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.warmup.v1";
+
+/// media type of the layer holding the reference input profile
+pub const PROFILE_MEDIA_TYPE: &str = "application/vnd.wasmcloud.mlinference.profile.v1+json";
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// the group of the layers of each media type
+const LAYER_GROUPS: &[(&str, &str)] = &[
+    (MODEL_MEDIA_TYPE, MODEL_GROUP),
+    (CPU_VARIANT_MEDIA_TYPE, CPU_VARIANT_GROUP),
+    (METADATA_MEDIA_TYPE, METADATA_GROUP),
+    (LABELS_MEDIA_TYPE, LABELS_GROUP),
+    (WARMUP_MEDIA_TYPE, "warmup"),
+    (PROFILE_MEDIA_TYPE, "profile"),
+];
+
+/// Settings of the OCI registries models are pulled from
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct OciSettings {
+    /// registries reached by plain http rather than https, e.g. 'localhost:5000'
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_insecure: Vec<String>,
+}
+
+/// A model in an OCI registry, 'registry/repository:tag' or 'registry/repository@digest'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciReference {
+    pub registry: String,
+    pub repository: String,
+    /// tag or digest of the manifest, 'latest' if not given
+    pub reference: String,
+}
+
+impl OciReference {
+    pub fn parse(id: &str) -> Result<Self, OciError> {
+        let invalid = |reason: &str| OciError::InvalidReference(id.to_string(), reason.to_string());
+
+        let (registry, path) = id
+            .split_once('/')
+            .ok_or_else(|| invalid("expecting 'registry/repository:tag'"))?;
+        let (repository, reference) = match path.split_once('@') {
+            Some((_, digest)) if !digest.starts_with("sha256:") => {
+                return Err(invalid("only 'sha256' digests are supported"))
+            }
+            Some((repository, digest)) => (repository, digest),
+            None => match path.rsplit_once(':') {
+                Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                _ => (path, "latest"),
+            },
+        };
+
+        if registry.is_empty() || repository.is_empty() || reference.is_empty() {
+            return Err(invalid("expecting 'registry/repository:tag'"));
+        }
+        if !repository
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-/".contains(c))
+        {
+            return Err(invalid("repository names are lowercase"));
+        }
+
+        Ok(OciReference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+
+    /// the sha256 the manifest is referenced by, if not by tag
+    fn digest(&self) -> Option<&str> {
+        self.reference.strip_prefix("sha256:")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+impl Descriptor {
+    /// the layer as parcel of the group of its media type, None for other media types
+    fn to_parcel(&self) -> Result<Option<bindle::Parcel>, String> {
+        let group = match LAYER_GROUPS.iter().find(|(m, _)| *m == self.media_type) {
+            Some((_, group)) => group,
+            None => return Ok(None),
+        };
+        let sha256 = self
+            .digest
+            .strip_prefix("sha256:")
+            .ok_or_else(|| format!("layer digest '{}' is not a sha256", self.digest))?;
+
+        let mut annotations = self.annotations.clone();
+        let name = annotations
+            .remove(TITLE_ANNOTATION)
+            .unwrap_or_else(|| self.digest.clone());
+
+        Ok(Some(
+            ParcelFile {
+                name,
+                media_type: self.media_type.clone(),
+                sha256: sha256.to_string(),
+                size: self.size,
+                groups: vec![group.to_string()],
+                annotations,
+            }
+            .to_parcel(),
+        ))
+    }
+}
+
+/// Loads models from OCI registries
+pub struct OciModelLoader {
+    settings: OciSettings,
+    downloads: DownloadSettings,
+    allow_unsigned: bool,
+}
+
+impl OciModelLoader {
+    /// 'allow_unsigned' must be set, signatures of OCI artifacts are not verified
+    pub fn new(settings: OciSettings, downloads: DownloadSettings, allow_unsigned: bool) -> Self {
+        OciModelLoader {
+            settings,
+            downloads,
+            allow_unsigned,
+        }
+    }
+
+    /// the url of the distribution API of the reference's registry
+    fn base_url(&self, reference: &OciReference) -> String {
+        let scheme = if self.settings.allowed_insecure.contains(&reference.registry) {
+            "http"
+        } else {
+            "https"
+        };
+
+        format!(
+            "{}://{}/v2/{}",
+            scheme, reference.registry, reference.repository
+        )
+    }
+
+    fn downloader(&self, reference: &OciReference) -> BindleResult<Downloader> {
+        Downloader::new(&self.base_url(reference), self.downloads.clone())
+    }
+}
+
+#[async_trait]
+impl ModelLoader for OciModelLoader {
+    async fn get_parcels(&self, id: &str) -> BindleResult<(Vec<bindle::Parcel>, Option<String>)> {
+        let reference = OciReference::parse(id)?;
+
+        if !self.allow_unsigned {
+            log::error!("'{}' cannot be verified, OCI artifacts are not signed!", id);
+            return Err(OciError::Unverified(id.to_string()).into());
+        }
+
+        let url = format!(
+            "{}/manifests/{}",
+            self.base_url(&reference),
+            reference.reference
+        );
+        let manifest = self
+            .downloader(&reference)?
+            .get_document(&url, MANIFEST_MEDIA_TYPE)
+            .await?
+            .ok_or_else(|| {
+                log::error!("OCI manifest of '{}' not found!", id);
+                OciError::ManifestNotFound(id.to_string())
+            })?;
+
+        if let Some(digest) = reference.digest() {
+            if sha256_hex(&manifest) != digest {
+                log::error!("OCI manifest of '{}' does not match its digest", id);
+                return Err(OciError::InvalidManifest(
+                    id.to_string(),
+                    "content does not match its digest".to_string(),
+                )
+                .into());
+            }
+        }
+
+        let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|e| {
+            log::error!("OCI manifest of '{}' could not be parsed: {}", id, e);
+            OciError::InvalidManifest(id.to_string(), e.to_string())
+        })?;
+
+        let mut parcels = Vec::new();
+        for layer in manifest.layers.iter() {
+            match layer.to_parcel() {
+                Ok(Some(parcel)) => parcels.push(parcel),
+                Ok(None) => log::debug!(
+                    "ignoring layer '{}' of media type '{}'",
+                    layer.digest,
+                    layer.media_type
+                ),
+                Err(reason) => {
+                    log::error!("OCI manifest of '{}': {}", id, reason);
+                    return Err(OciError::InvalidManifest(id.to_string(), reason).into());
+                }
+            }
+        }
+
+        Ok((parcels, None))
+    }
+
+    async fn get_parcel(&self, id: &str, parcel: &bindle::Parcel) -> BindleResult<Vec<u8>> {
+        let reference = OciReference::parse(id)?;
+        let url = format!(
+            "{}/blobs/sha256:{}",
+            self.base_url(&reference),
+            parcel.label.sha256
+        );
+
+        self.downloader(&reference)?.get_blob(&url, parcel).await
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum OciError {
+    #[error("invalid OCI reference '{0}': {1}")]
+    InvalidReference(String, String),
+
+    #[error("OCI manifest of '{0}' was not found")]
+    ManifestNotFound(String),
+
+    #[error("invalid OCI manifest of '{0}': {1}")]
+    InvalidManifest(String, String),
+
+    #[error("'{0}' cannot be verified, set 'signatures.allow_unsigned' to load OCI artifacts")]
+    Unverified(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bindle_loader::BindleError, BindleLoader, VariantPreference};
+    use std::{collections::HashMap, sync::Arc};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// serves the given paths, any other by status 404
+    async fn serve(documents: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let registry = listener.local_addr().unwrap().to_string();
+        let documents = Arc::new(documents);
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let documents = documents.clone();
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") && socket.read(&mut byte).await.unwrap() == 1
+                    {
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8_lossy(&head).to_string();
+                    let path = head.split(' ').nth(1).unwrap_or_default();
+
+                    let (status, body) = match documents.get(path) {
+                        Some(body) => ("200 OK", body.as_slice()),
+                        None => ("404 Not Found", &[][..]),
+                    };
+                    let header = format!(
+                        "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = socket.write_all(header.as_bytes()).await;
+                    let _ = socket.write_all(body).await;
+                });
+            }
+        });
+
+        registry
+    }
+
+    fn layer(media_type: &str, title: &str, data: &[u8]) -> serde_json::Value {
+        serde_json::json!({
+            "mediaType": media_type,
+            "digest": format!("sha256:{}", sha256_hex(data)),
+            "size": data.len(),
+            "annotations": { TITLE_ANNOTATION: title },
+        })
+    }
+
+    fn loader(registry: &str, allow_unsigned: bool) -> OciModelLoader {
+        let settings = OciSettings {
+            allowed_insecure: vec![registry.to_string()],
+        };
+        let downloads = DownloadSettings {
+            retries: 0,
+            ..Default::default()
+        };
+
+        OciModelLoader::new(settings, downloads, allow_unsigned)
+    }
+
+    #[test]
+    fn references_are_parsed() {
+        let reference = OciReference::parse("localhost:5000/models/mobilenetv2-7:0.1.0").unwrap();
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.repository, "models/mobilenetv2-7");
+        assert_eq!(reference.reference, "0.1.0");
+
+        let reference = OciReference::parse("localhost:5000/plus3").unwrap();
+        assert_eq!(reference.reference, "latest");
+
+        let reference = OciReference::parse("localhost:5000/plus3@sha256:0123").unwrap();
+        assert_eq!(reference.digest(), Some("0123"));
+
+        assert!(OciReference::parse("plus3:0.1.0").is_err());
+        assert!(OciReference::parse("localhost:5000/Plus3:0.1.0").is_err());
+        assert!(OciReference::parse("localhost:5000/plus3@md5:0123").is_err());
+    }
+
+    #[tokio::test]
+    async fn models_are_loaded_from_a_registry() {
+        let model = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let metadata = std::fs::read("../../bindle/models/identity_input_output.json").unwrap();
+        let labels = b"left\nright".to_vec();
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "config": layer("application/vnd.oci.empty.v1+json", "config", b"{}"),
+            "layers": [
+                layer(MODEL_MEDIA_TYPE, "identity_input_output.onnx", &model),
+                layer(METADATA_MEDIA_TYPE, "identity_input_output.json", &metadata),
+                layer(LABELS_MEDIA_TYPE, "labels.txt", &labels),
+                layer("application/vnd.unknown.v1", "readme.md", b"ignored"),
+            ],
+        }))
+        .unwrap();
+
+        let mut documents = HashMap::new();
+        for data in [&model, &metadata, &labels] {
+            documents.insert(
+                format!("/v2/models/identity/blobs/sha256:{}", sha256_hex(data)),
+                data.clone(),
+            );
+        }
+        documents.insert("/v2/models/identity/manifests/0.1.0".to_string(), manifest);
+        let registry = serve(documents).await;
+        let loader = loader(&registry, true);
+        let id = format!("{}/models/identity:0.1.0", registry);
+
+        let (metadata, model_data) = BindleLoader::get_model_and_metadata(
+            &loader,
+            &id,
+            &VariantPreference::default(),
+            |_, _| true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(model_data, model);
+        assert_eq!(metadata.tensor_type, "F32");
+        assert_eq!(metadata.tensor_dimensions_in, Some(vec![1, 2, 3]));
+        assert_eq!(
+            metadata.labels,
+            Some(vec!["left".to_string(), "right".to_string()])
+        );
+        assert_eq!(metadata.signer, None);
+
+        let missing = format!("{}/models/identity:0.2.0", registry);
+        assert!(matches!(
+            loader.get_parcels(&missing).await,
+            Err(BindleError::Oci(OciError::ManifestNotFound(..)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn artifacts_are_refused_unless_unsigned_models_are_allowed() {
+        let loader = loader("localhost:5000", false);
+
+        assert!(matches!(
+            loader
+                .get_parcels("localhost:5000/models/identity:0.1.0")
+                .await,
+            Err(BindleError::Oci(OciError::Unverified(..)))
+        ));
+    }
+}
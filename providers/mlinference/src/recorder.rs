@@ -0,0 +1,544 @@
+//! Recording of inference requests and responses
+//!
+//! A recording file starts with the 7 byte magic `MLREC\0\0` followed by
+//! the format version as u8 (currently 1). The header is followed by records,
+//! each of them prefixed with its length in bytes as u32. All integers are
+//! little endian, a `string` is a u32 length followed by as many UTF-8 bytes.
+//!
+//! | field              | encoding                                         |
+//! |--------------------|--------------------------------------------------|
+//! | timestamp          | u64, milliseconds since unix epoch               |
+//! | model              | string, model name as used by the actor          |
+//! | bindle id          | string, e.g. `mobilenetv2-7/0.1.0`               |
+//! | engine             | string, e.g. `Tract`                             |
+//! | graph encoding     | string, JSON encoding, e.g. `"onnx"`             |
+//! | execution target   | string, JSON encoding, e.g. `"cpu"`              |
+//! | index              | u32                                              |
+//! | input tensor       | tensor                                           |
+//! | output status      | string, JSON encoding of `Status`                |
+//! | output tensor      | tensor                                           |
+//!
+//! A tensor is encoded as its value types (string, JSON encoding of
+//! `Vec<ValueType>`), its number of dimensions as u32 followed by each
+//! dimension as u32, its flags (string, JSON encoding) and its data as u32
+//! length followed by the raw bytes.
+//!
+//! Once a file exceeds `max_file_bytes` it is rotated: `<name>.mlrec`
+//! becomes `<name>.mlrec.1`, `<name>.mlrec.1` becomes `<name>.mlrec.2` and so
+//! forth, keeping at most `max_files` files.
+
+use crate::{ExecutionTarget, GraphEncoding};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use wasmcloud_interface_mlinference::{InferenceOutput, Status, Tensor, ValueType};
+
+pub const RECORDING_MAGIC: &[u8; 7] = b"MLREC\0\0";
+pub const RECORDING_VERSION: u8 = 1;
+pub const RECORDING_EXTENSION: &str = "mlrec";
+
+/// Settings of the request/response recorder
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecorderSettings {
+    /// directory the recordings are written to, recording is disabled if not set
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// fraction of requests being recorded, between 0 and 1
+    #[serde(default = "RecorderSettings::default_sample_rate")]
+    pub sample_rate: f64,
+
+    /// size in bytes after which a recording file is rotated
+    #[serde(default = "RecorderSettings::default_max_file_bytes")]
+    pub max_file_bytes: u64,
+
+    /// maximum number of recording files per actor, including the current one
+    #[serde(default = "RecorderSettings::default_max_files")]
+    pub max_files: usize,
+}
+
+impl RecorderSettings {
+    fn default_sample_rate() -> f64 {
+        1.0
+    }
+
+    fn default_max_file_bytes() -> u64 {
+        64 * 1024 * 1024
+    }
+
+    fn default_max_files() -> usize {
+        4
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some() && self.sample_rate > 0.0
+    }
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        RecorderSettings {
+            path: None,
+            sample_rate: RecorderSettings::default_sample_rate(),
+            max_file_bytes: RecorderSettings::default_max_file_bytes(),
+            max_files: RecorderSettings::default_max_files(),
+        }
+    }
+}
+
+impl Eq for RecorderSettings {}
+
+/// A recorded inference
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub timestamp_ms: u64,
+    pub model: String,
+    pub bindle_id: String,
+    pub engine: String,
+    pub graph_encoding: GraphEncoding,
+    pub execution_target: ExecutionTarget,
+    pub index: u32,
+    pub input: Tensor,
+    pub output: InferenceOutput,
+}
+
+impl Record {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut body: Vec<u8> = Vec::new();
+        body.write_u64::<LittleEndian>(self.timestamp_ms)?;
+        write_string(&mut body, &self.model)?;
+        write_string(&mut body, &self.bindle_id)?;
+        write_string(&mut body, &self.engine)?;
+        write_string(&mut body, &to_json(&self.graph_encoding)?)?;
+        write_string(&mut body, &to_json(&self.execution_target)?)?;
+        body.write_u32::<LittleEndian>(self.index)?;
+        write_tensor(&mut body, &self.input)?;
+        write_string(&mut body, &to_json(&self.output.result)?)?;
+        write_tensor(&mut body, &self.output.tensor)?;
+
+        w.write_u32::<LittleEndian>(body.len() as u32)?;
+        w.write_all(&body)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Option<Record>> {
+        let len = match r.read_u32::<LittleEndian>() {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut body = vec![0u8; len as usize];
+        r.read_exact(&mut body)?;
+        let mut body = Cursor::new(body);
+
+        Ok(Some(Record {
+            timestamp_ms: body.read_u64::<LittleEndian>()?,
+            model: read_string(&mut body)?,
+            bindle_id: read_string(&mut body)?,
+            engine: read_string(&mut body)?,
+            graph_encoding: from_json(&read_string(&mut body)?)?,
+            execution_target: from_json(&read_string(&mut body)?)?,
+            index: body.read_u32::<LittleEndian>()?,
+            input: read_tensor(&mut body)?,
+            output: InferenceOutput {
+                result: from_json::<Status>(&read_string(&mut body)?)?,
+                tensor: read_tensor(&mut body)?,
+            },
+        }))
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> io::Result<String> {
+    serde_json::to_string(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(s: &str) -> io::Result<T> {
+    serde_json::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = r.read_u32::<LittleEndian>()?;
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_tensor<W: Write>(w: &mut W, tensor: &Tensor) -> io::Result<()> {
+    write_string(w, &to_json(&tensor.value_types)?)?;
+    w.write_u32::<LittleEndian>(tensor.dimensions.len() as u32)?;
+    for d in tensor.dimensions.iter() {
+        w.write_u32::<LittleEndian>(*d)?;
+    }
+    write_string(w, &to_json(&tensor.flags)?)?;
+    w.write_u32::<LittleEndian>(tensor.data.len() as u32)?;
+    w.write_all(&tensor.data)
+}
+
+fn read_tensor<R: Read>(r: &mut R) -> io::Result<Tensor> {
+    let value_types = from_json(&read_string(r)?)?;
+    let rank = r.read_u32::<LittleEndian>()?;
+    let dimensions = (0..rank)
+        .map(|_| r.read_u32::<LittleEndian>())
+        .collect::<io::Result<Vec<u32>>>()?;
+    let flags = from_json(&read_string(r)?)?;
+    let len = r.read_u32::<LittleEndian>()?;
+    let mut data = vec![0u8; len as usize];
+    r.read_exact(&mut data)?;
+
+    Ok(Tensor {
+        value_types,
+        dimensions,
+        flags,
+        data,
+    })
+}
+
+/// Reads all records of a recording file
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<Record>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 7];
+    reader.read_exact(&mut magic)?;
+    let version = reader.read_u8()?;
+    if &magic != RECORDING_MAGIC || version != RECORDING_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a recording of version {}", RECORDING_VERSION),
+        ));
+    }
+
+    let mut records = Vec::new();
+    while let Some(record) = Record::read_from(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Numerical difference between a recorded and a replayed output tensor
+#[derive(Debug, Clone, PartialEq)]
+pub enum TensorDiff {
+    /// dimensions or value types differ, values are not comparable
+    ShapeMismatch {
+        recorded: Vec<u32>,
+        replayed: Vec<u32>,
+    },
+    /// element-wise comparison of f32 tensors
+    Values {
+        max_abs: f32,
+        mean_abs: f32,
+        elements: usize,
+    },
+    /// byte-wise comparison of tensors of other value types
+    Bytes { differing: usize, elements: usize },
+}
+
+impl TensorDiff {
+    pub fn between(recorded: &Tensor, replayed: &Tensor) -> TensorDiff {
+        if recorded.dimensions != replayed.dimensions
+            || recorded.value_types != replayed.value_types
+            || recorded.data.len() != replayed.data.len()
+        {
+            return TensorDiff::ShapeMismatch {
+                recorded: recorded.dimensions.clone(),
+                replayed: replayed.dimensions.clone(),
+            };
+        }
+
+        if recorded.value_types == [ValueType::ValueF32] {
+            let diffs: Vec<f32> = recorded
+                .data
+                .chunks_exact(4)
+                .zip(replayed.data.chunks_exact(4))
+                .map(|(a, b)| {
+                    let a = f32::from_le_bytes([a[0], a[1], a[2], a[3]]);
+                    let b = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                    (a - b).abs()
+                })
+                .collect();
+            let elements = diffs.len();
+            TensorDiff::Values {
+                max_abs: diffs.iter().copied().fold(0.0, f32::max),
+                mean_abs: if elements == 0 {
+                    0.0
+                } else {
+                    diffs.iter().sum::<f32>() / elements as f32
+                },
+                elements,
+            }
+        } else {
+            TensorDiff::Bytes {
+                differing: recorded
+                    .data
+                    .iter()
+                    .zip(replayed.data.iter())
+                    .filter(|(a, b)| a != b)
+                    .count(),
+                elements: recorded.data.len(),
+            }
+        }
+    }
+
+    /// true if the tensors are considered equal
+    pub fn within(&self, tolerance: f32) -> bool {
+        match self {
+            TensorDiff::ShapeMismatch { .. } => false,
+            TensorDiff::Values { max_abs, .. } => *max_abs <= tolerance,
+            TensorDiff::Bytes { differing, .. } => *differing == 0,
+        }
+    }
+}
+
+impl std::fmt::Display for TensorDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TensorDiff::ShapeMismatch { recorded, replayed } => write!(
+                f,
+                "shape mismatch: recorded {:?}, replayed {:?}",
+                recorded, replayed
+            ),
+            TensorDiff::Values {
+                max_abs,
+                mean_abs,
+                elements,
+            } => write!(
+                f,
+                "{} elements, max abs diff {:e}, mean abs diff {:e}",
+                elements, max_abs, mean_abs
+            ),
+            TensorDiff::Bytes {
+                differing,
+                elements,
+            } => write!(f, "{} of {} bytes differ", differing, elements),
+        }
+    }
+}
+
+struct RecorderFile {
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+/// Writes sampled inferences to a rotating recording file
+pub struct Recorder {
+    settings: RecorderSettings,
+    path: PathBuf,
+    file: Mutex<Option<RecorderFile>>,
+}
+
+impl Recorder {
+    /// recorder writing to `<settings.path>/<name>.mlrec`
+    pub fn new(settings: RecorderSettings, name: &str) -> io::Result<Self> {
+        let dir = PathBuf::from(settings.path.clone().unwrap_or_else(|| ".".to_string()));
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            path: dir.join(format!("{}.{}", name, RECORDING_EXTENSION)),
+            settings,
+            file: Mutex::new(None),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// decides whether the next request is recorded
+    pub fn sample(&self) -> bool {
+        self.settings.sample_rate >= 1.0 || rand::thread_rng().gen_bool(self.settings.sample_rate)
+    }
+
+    /// appends a record, rotating the file if it became too large
+    pub fn record(&self, record: &Record) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+
+        if file.is_none() {
+            *file = Some(self.open()?);
+        }
+
+        let current = file.as_mut().unwrap();
+        let mut buf = Vec::new();
+        record.write_to(&mut buf)?;
+        current.writer.write_all(&buf)?;
+        current.writer.flush()?;
+        current.written += buf.len() as u64;
+
+        if current.written >= self.settings.max_file_bytes {
+            *file = None;
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<RecorderFile> {
+        let f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut written = f.metadata()?.len();
+        let mut writer = BufWriter::new(f);
+
+        if written == 0 {
+            writer.write_all(RECORDING_MAGIC)?;
+            writer.write_u8(RECORDING_VERSION)?;
+            written = RECORDING_MAGIC.len() as u64 + 1;
+        }
+
+        Ok(RecorderFile { writer, written })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let max_files = self.settings.max_files.max(1);
+
+        if max_files == 1 {
+            return fs::remove_file(&self.path);
+        }
+
+        let oldest = self.rotated(max_files - 1);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for n in (1..max_files - 1).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+
+        log::info!("rotated recording file '{}'", self.path.display());
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("settings", &self.settings)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(i: u32) -> Record {
+        let tensor = Tensor {
+            value_types: vec![ValueType::ValueF32],
+            dimensions: vec![1, 2],
+            flags: 0,
+            data: vec![i as u8; 8],
+        };
+        Record {
+            timestamp_ms: 1_650_000_000_000 + i as u64,
+            model: "identity".to_string(),
+            bindle_id: "identity_model/0.2.0".to_string(),
+            engine: "Tract".to_string(),
+            graph_encoding: GraphEncoding::Onnx,
+            execution_target: ExecutionTarget::Cpu,
+            index: i,
+            input: tensor.clone(),
+            output: InferenceOutput {
+                result: Status::Success,
+                tensor,
+            },
+        }
+    }
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mlrec-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn records_survive_a_round_trip() {
+        let dir = tempdir("roundtrip");
+        let settings = RecorderSettings {
+            path: Some(dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let recorder = Recorder::new(settings, "actor").unwrap();
+
+        recorder.record(&record(0)).unwrap();
+        recorder.record(&record(1)).unwrap();
+
+        assert_eq!(
+            read_recording(recorder.path()).unwrap(),
+            vec![record(0), record(1)]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn f32_tensors_are_compared_numerically() {
+        let recorded = record(0).input;
+        let mut replayed = recorded.clone();
+        replayed.data = [0.0f32, 0.5f32]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+
+        let diff = TensorDiff::between(&recorded, &replayed);
+        assert_eq!(
+            diff,
+            TensorDiff::Values {
+                max_abs: 0.5,
+                mean_abs: 0.25,
+                elements: 2
+            }
+        );
+        assert!(diff.within(0.5));
+        assert!(!diff.within(0.1));
+
+        replayed.dimensions = vec![2, 1];
+        assert!(!TensorDiff::between(&recorded, &replayed).within(1.0));
+    }
+
+    #[test]
+    fn files_are_rotated() {
+        let dir = tempdir("rotate");
+        let settings = RecorderSettings {
+            path: Some(dir.to_string_lossy().to_string()),
+            max_file_bytes: 1,
+            max_files: 2,
+            ..Default::default()
+        };
+        let recorder = Recorder::new(settings, "actor").unwrap();
+
+        for i in 0..3 {
+            recorder.record(&record(i)).unwrap();
+        }
+
+        assert!(!recorder.path().exists());
+        assert!(recorder.rotated(1).exists());
+        assert!(!recorder.rotated(2).exists());
+        assert_eq!(read_recording(recorder.rotated(1)).unwrap()[0].index, 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,470 @@
+use crate::{
+    requires_edge_tpu, Engine, ExecutionTarget, Graph, GraphEncoding, GraphExecutionContext,
+    InferenceError, InferenceFramework, ModelMetadata, TractEngine, CPU_VARIANT_GROUP,
+};
+use std::sync::Arc;
+
+#[cfg(any(feature = "tflite", feature = "edgetpu"))]
+use crate::TfLiteEngine;
+
+#[cfg(feature = "tract-tflite")]
+use crate::TractTfLiteEngine;
+
+#[cfg(feature = "candle")]
+use crate::CandleEngine;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EngineSelectionError {
+    #[error("no engine of this build supports encoding '{0:?}' on target '{1:?}'")]
+    Unsupported(GraphEncoding, ExecutionTarget),
+
+    #[error("preferred framework '{0:?}' does not support encoding '{1:?}' on target '{2:?}'")]
+    PreferredUnsupported(InferenceFramework, GraphEncoding, ExecutionTarget),
+
+    #[error("no execution target is available: {0}")]
+    NoTargetAvailable(String),
+
+    #[error(transparent)]
+    Inference(#[from] InferenceError),
+}
+
+/// A model loaded and initialized by `EngineRegistry::initialize`
+pub struct InitializedModel {
+    pub framework: InferenceFramework,
+    pub engine: Engine,
+    pub graph: Graph,
+    pub graph_execution_context: GraphExecutionContext,
+    /// the target the model runs on, which is a fallback if the first is unavailable
+    pub execution_target: ExecutionTarget,
+}
+
+/// The engines of a provider, each declaring the encodings and targets it
+/// supports by `InferenceEngine::priority`. A model runs on the engine of the
+/// highest priority unless it prefers another framework.
+#[derive(Clone)]
+pub struct EngineRegistry {
+    engines: Vec<(InferenceFramework, Engine)>,
+}
+
+impl Default for EngineRegistry {
+    /// registry of the engines compiled into this build
+    fn default() -> Self {
+        let mut registry = EngineRegistry::empty();
+
+        registry.register(
+            InferenceFramework::Tract,
+            Arc::new(Box::new(TractEngine::default())),
+        );
+
+        #[cfg(any(feature = "tflite", feature = "edgetpu"))]
+        registry.register(
+            InferenceFramework::TfLite,
+            Arc::new(Box::new(TfLiteEngine::default())),
+        );
+
+        #[cfg(feature = "tract-tflite")]
+        registry.register(
+            InferenceFramework::TractTfLite,
+            Arc::new(Box::new(TractTfLiteEngine::default())),
+        );
+
+        #[cfg(feature = "candle")]
+        registry.register(
+            InferenceFramework::Candle,
+            Arc::new(Box::new(CandleEngine::default())),
+        );
+
+        registry
+    }
+}
+
+impl EngineRegistry {
+    /// registry without any engine
+    pub fn empty() -> Self {
+        EngineRegistry {
+            engines: Vec::new(),
+        }
+    }
+
+    /// adds an engine, replacing the one registered for the same framework
+    pub fn register(&mut self, framework: InferenceFramework, engine: Engine) {
+        self.engines.retain(|(f, _)| *f != framework);
+        self.engines.push((framework, engine));
+    }
+
+    /// the engine registered for a framework
+    pub fn get(&self, framework: &InferenceFramework) -> Option<Engine> {
+        self.engines
+            .iter()
+            .find(|(f, _)| f == framework)
+            .map(|(_, e)| e.clone())
+    }
+
+    /// frameworks of the registered engines
+    pub fn frameworks(&self) -> Vec<InferenceFramework> {
+        self.engines.iter().map(|(f, _)| f.clone()).collect()
+    }
+
+    /// Selects the engine for a model, the preferred framework if given, else
+    /// the engine of the highest priority. Ties go to the first registered.
+    pub fn select(
+        &self,
+        encoding: &GraphEncoding,
+        target: &ExecutionTarget,
+        preferred: Option<&InferenceFramework>,
+    ) -> Result<(InferenceFramework, Engine), EngineSelectionError> {
+        if let Some(preferred) = preferred {
+            return self
+                .engines
+                .iter()
+                .find(|(f, e)| f == preferred && e.priority(encoding, target).is_some())
+                .cloned()
+                .ok_or_else(|| {
+                    EngineSelectionError::PreferredUnsupported(
+                        preferred.clone(),
+                        encoding.clone(),
+                        target.clone(),
+                    )
+                });
+        }
+
+        let mut selected: Option<(u32, &(InferenceFramework, Engine))> = None;
+        for entry in self.engines.iter() {
+            if let Some(priority) = entry.1.priority(encoding, target) {
+                if selected.is_none_or(|(p, _)| priority > p) {
+                    selected = Some((priority, entry));
+                }
+            }
+        }
+
+        selected
+            .map(|(_, entry)| entry.clone())
+            .ok_or_else(|| EngineSelectionError::Unsupported(encoding.clone(), target.clone()))
+    }
+
+    /// Loads and initializes a model on the first of 'targets' which an engine
+    /// supports and a device is available for. On targets other than the TPU,
+    /// a model compiled for the Edge TPU is replaced by its 'cpu_variant'.
+    pub async fn initialize(
+        &self,
+        targets: &[ExecutionTarget],
+        preferred: Option<&InferenceFramework>,
+        metadata: &ModelMetadata,
+        model: &[u8],
+        cpu_variant: Option<&[u8]>,
+    ) -> Result<InitializedModel, EngineSelectionError> {
+        let mut skipped: Vec<String> = Vec::new();
+
+        for target in targets {
+            let (framework, engine) = match self.select(&metadata.graph_encoding, target, preferred)
+            {
+                Ok(selected) => selected,
+                Err(e) => {
+                    skipped.push(e.to_string());
+                    continue;
+                }
+            };
+
+            let model = match target {
+                ExecutionTarget::Tpu => model,
+                _ if !requires_edge_tpu(model) => model,
+                _ => match cpu_variant {
+                    Some(variant) => variant,
+                    None => {
+                        skipped.push(format!(
+                            "the model requires the Edge TPU and has no parcel of group '{}'",
+                            CPU_VARIANT_GROUP
+                        ));
+                        continue;
+                    }
+                },
+            };
+
+            let graph = engine.load(model).await?;
+            let initialized = match engine.configure(graph, metadata).await {
+                Ok(()) => {
+                    engine
+                        .init_execution_context(graph, target, &metadata.graph_encoding)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            let error = match initialized {
+                Ok(graph_execution_context) => {
+                    return Ok(InitializedModel {
+                        framework,
+                        engine,
+                        graph,
+                        graph_execution_context,
+                        execution_target: target.clone(),
+                    })
+                }
+                Err(e) => e,
+            };
+
+            // the loaded graph is not used on any path of failure
+            engine
+                .drop_model_state(&graph, &GraphExecutionContext::MAX)
+                .await;
+            match error {
+                InferenceError::DeviceNotFound(target) => {
+                    log::warn!("initialize() - no device for target '{:?}'", target);
+                    skipped.push(format!("no device for target '{:?}'", target));
+                }
+                e => return Err(e.into()),
+            }
+        }
+
+        Err(EngineSelectionError::NoTargetAvailable(skipped.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{InferenceEngine, InferenceResult};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wasmcloud_interface_mlinference::{InferenceOutput, Tensor};
+
+    /// engine claiming ONNX on the TPU while no TPU is attached
+    struct AbsentTpuEngine;
+
+    #[async_trait]
+    impl InferenceEngine for AbsentTpuEngine {
+        fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+            match (encoding, target) {
+                (GraphEncoding::Onnx, ExecutionTarget::Tpu) => Some(100),
+                _ => None,
+            }
+        }
+
+        async fn load(&self, _model: &[u8]) -> InferenceResult<Graph> {
+            Ok(0)
+        }
+
+        async fn init_execution_context(
+            &self,
+            _graph: Graph,
+            target: &ExecutionTarget,
+            _encoding: &GraphEncoding,
+        ) -> InferenceResult<GraphExecutionContext> {
+            Err(InferenceError::DeviceNotFound(target.clone()))
+        }
+
+        async fn set_input(
+            &self,
+            _context: GraphExecutionContext,
+            _index: u32,
+            _tensor: &Tensor,
+        ) -> InferenceResult<()> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn compute(&self, _context: GraphExecutionContext) -> InferenceResult<()> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn get_output(
+            &self,
+            _context: GraphExecutionContext,
+            _index: u32,
+        ) -> InferenceResult<InferenceOutput> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn drop_model_state(&self, _graph: &Graph, _gec: &GraphExecutionContext) {}
+    }
+
+    /// engine claiming ONNX on the GPU which fails to configure or to initialize a model
+    struct FailingGpuEngine {
+        configure_fails: bool,
+        drops: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl InferenceEngine for FailingGpuEngine {
+        fn priority(&self, encoding: &GraphEncoding, target: &ExecutionTarget) -> Option<u32> {
+            match (encoding, target) {
+                (GraphEncoding::Onnx, ExecutionTarget::Gpu) => Some(100),
+                _ => None,
+            }
+        }
+
+        async fn load(&self, _model: &[u8]) -> InferenceResult<Graph> {
+            Ok(0)
+        }
+
+        async fn configure(&self, _graph: Graph, _metadata: &ModelMetadata) -> InferenceResult<()> {
+            match self.configure_fails {
+                true => Err(InferenceError::InvalidEncodingError),
+                false => Ok(()),
+            }
+        }
+
+        async fn init_execution_context(
+            &self,
+            _graph: Graph,
+            _target: &ExecutionTarget,
+            _encoding: &GraphEncoding,
+        ) -> InferenceResult<GraphExecutionContext> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn set_input(
+            &self,
+            _context: GraphExecutionContext,
+            _index: u32,
+            _tensor: &Tensor,
+        ) -> InferenceResult<()> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn compute(&self, _context: GraphExecutionContext) -> InferenceResult<()> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn get_output(
+            &self,
+            _context: GraphExecutionContext,
+            _index: u32,
+        ) -> InferenceResult<InferenceOutput> {
+            Err(InferenceError::RuntimeError)
+        }
+
+        async fn drop_model_state(&self, _graph: &Graph, _gec: &GraphExecutionContext) {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn with_absent_tpu() -> EngineRegistry {
+        let mut registry = EngineRegistry::default();
+        registry.register(
+            InferenceFramework::TfLite,
+            Arc::new(Box::new(AbsentTpuEngine)),
+        );
+        registry
+    }
+
+    #[tokio::test]
+    async fn absent_device_falls_back_to_next_target() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let metadata = ModelMetadata {
+            graph_encoding: GraphEncoding::Onnx,
+            ..Default::default()
+        };
+
+        let model = with_absent_tpu()
+            .initialize(
+                &[ExecutionTarget::Tpu, ExecutionTarget::Cpu],
+                None,
+                &metadata,
+                &onnx,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(model.execution_target, ExecutionTarget::Cpu);
+        assert_eq!(model.framework, InferenceFramework::Tract);
+    }
+
+    #[tokio::test]
+    async fn exhausted_targets_are_reported() {
+        let onnx = std::fs::read("../../bindle/models/identity_input_output.onnx").unwrap();
+        let metadata = ModelMetadata {
+            graph_encoding: GraphEncoding::Onnx,
+            ..Default::default()
+        };
+
+        let result = with_absent_tpu()
+            .initialize(&[ExecutionTarget::Tpu], None, &metadata, &onnx, None)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(EngineSelectionError::NoTargetAvailable(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_initialization_drops_the_graph() {
+        let metadata = ModelMetadata {
+            graph_encoding: GraphEncoding::Onnx,
+            ..Default::default()
+        };
+
+        for configure_fails in [true, false] {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let mut registry = EngineRegistry::default();
+            registry.register(
+                InferenceFramework::TfLite,
+                Arc::new(Box::new(FailingGpuEngine {
+                    configure_fails,
+                    drops: drops.clone(),
+                })),
+            );
+
+            let result = registry
+                .initialize(&[ExecutionTarget::Gpu], None, &metadata, b"model", None)
+                .await;
+
+            assert!(matches!(result, Err(EngineSelectionError::Inference(..))));
+            assert_eq!(drops.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[test]
+    fn tract_is_selected_for_onnx() {
+        let registry = EngineRegistry::default();
+        let (framework, _) = registry
+            .select(&GraphEncoding::Onnx, &ExecutionTarget::Cpu, None)
+            .unwrap();
+
+        assert_eq!(framework, InferenceFramework::Tract);
+    }
+
+    #[test]
+    fn unsupported_target_is_rejected() {
+        let registry = EngineRegistry::default();
+
+        assert!(matches!(
+            registry.select(&GraphEncoding::Onnx, &ExecutionTarget::Gpu, None),
+            Err(EngineSelectionError::Unsupported(..))
+        ));
+    }
+
+    #[test]
+    fn preferred_framework_must_support_the_model() {
+        let registry = EngineRegistry::default();
+
+        assert!(matches!(
+            registry.select(
+                &GraphEncoding::Onnx,
+                &ExecutionTarget::Cpu,
+                Some(&InferenceFramework::Candle)
+            ),
+            Err(EngineSelectionError::PreferredUnsupported(..))
+        ));
+    }
+
+    #[cfg(all(feature = "tflite", feature = "tract-tflite"))]
+    #[test]
+    fn tflite_can_be_forced_onto_tract() {
+        let registry = EngineRegistry::default();
+        let (native, _) = registry
+            .select(&GraphEncoding::TfLite, &ExecutionTarget::Cpu, None)
+            .unwrap();
+        let (forced, _) = registry
+            .select(
+                &GraphEncoding::TfLite,
+                &ExecutionTarget::Cpu,
+                Some(&InferenceFramework::TractTfLite),
+            )
+            .unwrap();
+
+        assert_eq!(native, InferenceFramework::TfLite);
+        assert_eq!(forced, InferenceFramework::TractTfLite);
+    }
+}
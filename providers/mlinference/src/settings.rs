@@ -1,9 +1,15 @@
 //use hashmap_ci::{make_case_insensitive};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, str::FromStr};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+};
 use wasmbus_rpc::error::RpcError;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct ModelSettings {
     /// model to path assignments
     #[serde(default)]
@@ -11,6 +17,45 @@ pub struct ModelSettings {
 
     /// loading models before first compute or at linkage
     pub lazy_load: Option<bool>,
+
+    /// bounds for in-flight and queued requests, rate limit per actor
+    #[serde(default)]
+    pub admission: crate::AdmissionSettings,
+
+    /// warm-up inferences run at linkage
+    #[serde(default)]
+    pub warmup: crate::WarmupSettings,
+
+    /// per-model cache of inference results
+    #[serde(default)]
+    pub cache: crate::CacheSettings,
+
+    /// sampled recording of requests and responses
+    #[serde(default)]
+    pub recording: crate::RecorderSettings,
+
+    /// input drift and data-quality monitoring
+    #[serde(default)]
+    pub monitoring: crate::MonitorSettings,
+
+    /// options of the TfLite interpreter, taking precedence over those of the models' metadata
+    #[serde(default)]
+    pub tflite: crate::TfLiteOptions,
+
+    /// framework to run all models on, taking precedence over the models' metadata
+    pub preferred_framework: Option<crate::InferenceFramework>,
+
+    /// verification of the models' invoices against trusted keys
+    #[serde(default)]
+    pub signatures: crate::SignatureSettings,
+
+    /// retries, timeouts and caching of downloads from the bindle server
+    #[serde(default)]
+    pub downloads: crate::DownloadSettings,
+
+    /// registries of models referenced by 'oci://'
+    #[serde(default)]
+    pub oci: crate::OciSettings,
 }
 
 impl Default for ModelSettings {
@@ -18,6 +63,16 @@ impl Default for ModelSettings {
         ModelSettings {
             models: Models::default(),
             lazy_load: Some(false),
+            admission: Default::default(),
+            warmup: Default::default(),
+            cache: Default::default(),
+            recording: Default::default(),
+            monitoring: Default::default(),
+            tflite: Default::default(),
+            preferred_framework: None,
+            signatures: Default::default(),
+            downloads: Default::default(),
+            oci: Default::default(),
         }
     }
 }
@@ -26,15 +81,281 @@ impl ModelSettings {
     /// perform additional validation checks on settings.
     /// Several checks have already been done during deserialization.
     /// All errors found are combined into a single error message
-    fn validate(&self) -> Result<(), RpcError> {
-        Ok(())
+    pub fn validate(&self) -> Result<(), RpcError> {
+        let problems = self.problems();
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            log::error!("invalid settings: {}", problems.join("; "));
+            Err(RpcError::ProviderInit(format!(
+                "invalid settings: {}",
+                problems.join("; ")
+            )))
+        }
+    }
+
+    /// problems of the settings, none if valid
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.models.is_empty() {
+            problems.push("no model configured in 'models.zoo'".to_string());
+        }
+
+        let mut references = HashSet::new();
+        for (name, entry) in self.models.zoo.iter() {
+            let reference = entry.reference();
+            let options = entry.options();
+
+            if name.trim().is_empty() {
+                problems.push(format!("empty model name for '{}'", reference));
+            }
+
+            if let Err(e) = validate_reference(reference) {
+                problems.push(format!("model '{}': {}", name, e));
+            }
+
+            if !references.insert(split_reference(reference).1) {
+                problems.push(format!(
+                    "model '{}': '{}' is referenced by another model as well",
+                    name, reference
+                ));
+            }
+
+            match (options.threads, &options.execution_target) {
+                (Some(threads), _) if threads < 1 => {
+                    problems.push(format!("model '{}': 'threads' must be positive", name))
+                }
+                (Some(threads), Some(crate::ExecutionTarget::Tpu)) if threads > 1 => {
+                    problems.push(format!(
+                        "model '{}': the Edge TPU runs with a single thread, not {}",
+                        name, threads
+                    ))
+                }
+                _ => (),
+            }
+
+            if let Some(fallback_targets) = &options.fallback_targets {
+                for (i, target) in fallback_targets.iter().enumerate() {
+                    if fallback_targets[..i].contains(target)
+                        || options.execution_target.as_ref() == Some(target)
+                    {
+                        problems.push(format!(
+                            "model '{}': target '{:?}' occurs more than once in 'fallback_targets'",
+                            name, target
+                        ));
+                    }
+                }
+            }
+
+            let lazy_load = options.lazy_load.or(self.lazy_load) == Some(true);
+            let warmup = options.warmup.as_ref().unwrap_or(&self.warmup);
+            if lazy_load && warmup.is_enabled() {
+                problems.push(format!(
+                    "model '{}': 'warmup' runs at linkage and conflicts with 'lazy_load'",
+                    name
+                ));
+            }
+        }
+
+        for name in self.admission.models.keys() {
+            if !self.models.zoo.contains_key(name) {
+                problems.push(format!("admission limits for unknown model '{}'", name));
+            }
+        }
+
+        for (name, limits) in std::iter::once(("(default)", &self.admission.limits)).chain(
+            self.admission
+                .models
+                .iter()
+                .map(|(name, limits)| (name.as_str(), limits)),
+        ) {
+            if limits.queue_depth.is_some() && limits.max_in_flight.is_none() {
+                problems.push(format!(
+                    "admission of model '{}': 'queue_depth' requires 'max_in_flight'",
+                    name
+                ));
+            }
+        }
+
+        if let Some(rate_limit) = &self.admission.rate_limit {
+            if rate_limit.requests_per_second <= 0.0 || rate_limit.burst == 0 {
+                problems.push(
+                    "'rate_limit' requires positive 'requests_per_second' and 'burst'".to_string(),
+                );
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.recording.sample_rate) {
+            problems.push("'recording.sample_rate' must be between 0 and 1".to_string());
+        }
+
+        if self.monitoring.enabled && self.monitoring.window == 0 {
+            problems.push("'monitoring.window' must be positive".to_string());
+        }
+
+        if let Some(keyring) = &self.signatures.keyring {
+            if !keyring.is_file() {
+                problems.push(format!(
+                    "'signatures.keyring' '{}' is not a file",
+                    keyring.display()
+                ));
+            }
+        }
+
+        if self.downloads.timeout_ms == 0 {
+            problems.push("'downloads.timeout_ms' must be positive".to_string());
+        }
+
+        if self.downloads.backoff_ms > self.downloads.max_backoff_ms {
+            problems.push(
+                "'downloads.backoff_ms' must not exceed 'downloads.max_backoff_ms'".to_string(),
+            );
+        }
+
+        problems
+    }
+}
+
+/// URI schemes of model references, a reference without scheme is a bindle id
+pub const MODEL_SCHEMES: &[&str] = &["bindle", "oci"];
+
+/// splits a model reference into its scheme and id, e.g. 'bindle://plus3/0.1.0'
+pub fn split_reference(reference: &str) -> (&str, &str) {
+    match reference.split_once("://") {
+        Some((scheme, id)) => (scheme, id),
+        None => ("bindle", reference),
+    }
+}
+
+/// the id a model is known by, its bindle id or else its full reference
+pub fn model_id(reference: &str) -> &str {
+    match split_reference(reference) {
+        ("bindle", id) => id,
+        _ => reference,
+    }
+}
+
+/// checks the scheme of a model reference and its id, 'name/semver' for bindles
+fn validate_reference(reference: &str) -> Result<(), String> {
+    let (scheme, id) = split_reference(reference);
+
+    match scheme {
+        "bindle" => bindle::Id::try_from(id)
+            .map(|_| ())
+            .map_err(|e| format!("malformed bindle id '{}': {}", id, e)),
+        "oci" => crate::OciReference::parse(id)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        _ => Err(format!(
+            "unknown scheme '{}' of '{}', expecting one of {:?}",
+            scheme, reference, MODEL_SCHEMES
+        )),
     }
 }
 
 //#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Models {
-    pub zoo: HashMap<crate::ModelName, crate::BindlePath>,
+    pub zoo: HashMap<crate::ModelName, ZooEntry>,
+}
+
+/// A model of the zoo, given by its reference or by its reference with options
+/// overriding those of the link, e.g.
+///
+/// {"reference": "mobilenetv1_quant_edgetpu/0.1.0", "execution_target": "tpu", "threads": 1}
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ZooEntry {
+    Reference(crate::BindlePath),
+    Options(ModelOptions),
+}
+
+// rather than untagged, such that errors name the offending field of the options
+impl<'de> Deserialize<'de> for ZooEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(reference) => Ok(ZooEntry::Reference(reference)),
+            options => ModelOptions::deserialize(options)
+                .map(ZooEntry::Options)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl ZooEntry {
+    /// the model's reference, e.g. 'plus3/0.1.0'
+    pub fn reference(&self) -> &str {
+        match self {
+            ZooEntry::Reference(reference) => reference,
+            ZooEntry::Options(options) => &options.reference,
+        }
+    }
+
+    /// the model's options, none but its reference for the plain form
+    pub fn options(&self) -> ModelOptions {
+        match self {
+            ZooEntry::Reference(reference) => ModelOptions {
+                reference: reference.clone(),
+                ..Default::default()
+            },
+            ZooEntry::Options(options) => options.clone(),
+        }
+    }
+}
+
+impl From<&str> for ZooEntry {
+    fn from(reference: &str) -> Self {
+        ZooEntry::Reference(reference.to_string())
+    }
+}
+
+/// Options of a single model, taking precedence over those of the link and the model's metadata.
+/// Batching is out of scope: each request of the interface is computed on its own, so there is
+/// no batching option and one given is refused as an unknown field.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ModelOptions {
+    /// model reference, e.g. 'plus3/0.1.0'
+    pub reference: crate::BindlePath,
+
+    /// target to run the model on rather than the one of its metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_target: Option<crate::ExecutionTarget>,
+
+    /// targets tried in order if no device of the execution target is available, e.g. '["cpu"]'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_targets: Option<Vec<crate::ExecutionTarget>>,
+
+    /// precision of the model variant preferred among those of the same target, e.g. 'uint8'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<String>,
+
+    /// framework to run the model on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_framework: Option<crate::InferenceFramework>,
+
+    /// number of threads of the TfLite interpreter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<i32>,
+
+    /// cache of the model's results, replacing the link's cache settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<crate::CacheSettings>,
+
+    /// warm-up of the model, replacing the link's warm-up settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<crate::WarmupSettings>,
+
+    /// time in milliseconds after which an inference is answered by an error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// overrides the link's 'lazy_load'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lazy_load: Option<bool>,
 }
 
 impl Models {
@@ -43,65 +364,349 @@ impl Models {
     }
 }
 
+/// prefix of environment variables overriding single settings, nested keys
+/// are separated by '__', e.g. 'MLINFERENCE_MODELS__ZOO__MOBILENET'
+pub const ENV_PREFIX: &str = "MLINFERENCE_";
+
+/// Merges the settings of all sources, each overriding the ones before:
+///
+/// 1. `config_file`, the path of a JSON or TOML file on the provider's host
+/// 2. `config_b64`, base64 encoded JSON
+/// 3. `config_json`
+/// 4. `config_toml`
+/// 5. `lazy_load`
+/// 6. environment variables prefixed by `MLINFERENCE_`
+///
+/// Objects are merged key by key, any other value is replaced.
 pub fn load_settings(values: &HashMap<String, String>) -> Result<ModelSettings, RpcError> {
+    load_settings_with_env(values, std::env::vars())
+}
+
+fn load_settings_with_env(
+    values: &HashMap<String, String>,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<ModelSettings, RpcError> {
     log::debug!("load_settings() - entering");
 
     // Allow keys to be UPPERCASE, as an accommodation
     // for the lost souls who prefer ugly all-caps variable names.
     let values = crate::make_case_insensitive(values).ok_or_else(|| {
         RpcError::InvalidParameter(
-            "Key collision: httpserver settings (from linkdef.values) has one or more keys that \
+            "Key collision: mlinference settings (from linkdef.values) has one or more keys that \
              are not unique based on case-insensitivity"
                 .to_string(),
         )
     })?;
 
-    let mut settings = ModelSettings::default();
-
-    log::debug!("load_settings() - settings: '{:?}'", &settings);
+    let mut merged = Value::Object(Default::default());
 
-    // log::debug!(
-    //     "load_settings() -2b------------- '{:?}'",
-    //     values.get("config_b64")
-    // );
+    if let Some(path) = values.get("config_file") {
+        merge(&mut merged, read_settings_file(Path::new(path.as_str()))?);
+    }
 
     if let Some(cj) = values.get("config_b64") {
-        settings = serde_json::from_slice(&base64::decode(cj).map_err(|e| {
+        let config = serde_json::from_slice(&base64::decode(cj).map_err(|e| {
             log::error!("base64 decode failed: {}", &e.to_string());
             RpcError::ProviderInit(format!("b64 encoding: {}", e))
         })?)
         .map_err(|e| {
             log::error!("deserialization failed: {}", &e.to_string());
-            RpcError::ProviderInit(format!(
-                "config_base64 had invalid struct: {}",
-                &e.to_string()
-            ))
-        })?
+            RpcError::ProviderInit(format!("config_b64 is invalid json: {}", &e.to_string()))
+        })?;
+        merge(&mut merged, config);
     }
 
-    // log::debug!(
-    //     "load_settings() -3b------------- settings: '{:?}'",
-    //     &settings
-    // );
-
     if let Some(cj) = values.get("config_json") {
-        settings = serde_json::from_str(cj.as_str()).map_err(|e| {
+        let config = serde_json::from_str(cj.as_str()).map_err(|e| {
             log::error!("invalid JSON config '{:?}'", cj);
             RpcError::ProviderInit(format!("invalid json config: {}", e))
         })?;
+        merge(&mut merged, config);
+    }
+
+    if let Some(ct) = values.get("config_toml") {
+        merge(&mut merged, toml_to_json(ct.as_str(), "config_toml")?);
     }
 
     if let Some(lazy_load) = values.get("lazy_load") {
-        settings.lazy_load = FromStr::from_str(lazy_load).ok();
+        if let Ok(lazy_load) = bool::from_str(lazy_load) {
+            merge(&mut merged, serde_json::json!({ "lazy_load": lazy_load }));
+        }
+    }
+
+    for (key, value) in env.into_iter() {
+        // provider-wide settings are not settings of a link
+        if key.starts_with(crate::MEMORY_ENV_PREFIX) {
+            continue;
+        }
+        if let Some(path) = key.strip_prefix(ENV_PREFIX) {
+            log::debug!(
+                "load_settings() - overriding by environment variable '{}'",
+                key
+            );
+            merge(&mut merged, env_override(path, &value));
+        }
     }
 
-    if settings.models.is_empty() {
-        log::error!("link params values are missing 'uri'");
-        Err(RpcError::ProviderInit(
-            "link params values are missing 'uri'".into(),
-        ))
-    } else {
-        settings.validate()?;
-        Ok(settings)
+    let settings: ModelSettings = serde_json::from_value(merged).map_err(|e| {
+        log::error!("invalid settings: {}", e);
+        RpcError::ProviderInit(format!("invalid settings: {}", e))
+    })?;
+
+    log::debug!("load_settings() - settings: '{:?}'", &settings);
+
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Reads and validates settings from a JSON or, by extension '.toml', TOML file
+pub fn load_settings_file(path: &Path) -> Result<ModelSettings, RpcError> {
+    let settings: ModelSettings =
+        serde_json::from_value(read_settings_file(path)?).map_err(|e| {
+            RpcError::ProviderInit(format!("invalid config '{}': {}", path.display(), e))
+        })?;
+
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn read_settings_file(path: &Path) -> Result<Value, RpcError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| RpcError::ProviderInit(format!("cannot read '{}': {}", path.display(), e)))?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml_to_json(&content, &path.display().to_string()),
+        _ => serde_json::from_str(&content).map_err(|e| {
+            RpcError::ProviderInit(format!("invalid json config '{}': {}", path.display(), e))
+        }),
+    }
+}
+
+fn toml_to_json(content: &str, source: &str) -> Result<Value, RpcError> {
+    toml::from_str::<toml::Value>(content)
+        .map_err(|e| e.to_string())
+        .and_then(|t| serde_json::to_value(t).map_err(|e| e.to_string()))
+        .map_err(|e| RpcError::ProviderInit(format!("invalid toml config '{}': {}", source, e)))
+}
+
+/// the object setting a single value, e.g. 'MODELS__ZOO__MOBILENET=mobilenetv2-7/0.1.0'
+/// sets {"models":{"zoo":{"mobilenet":"mobilenetv2-7/0.1.0"}}}. Values are taken
+/// as JSON if they parse as such, as string otherwise.
+fn env_override(path: &str, value: &str) -> Value {
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    path.rsplit("__").fold(value, |inner, key| {
+        let mut object = serde_json::Map::new();
+        object.insert(key.to_ascii_lowercase(), inner);
+        Value::Object(object)
+    })
+}
+
+/// merges 'other' into 'base', recursively for objects
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn with_zoo(models: &[(&str, &str)]) -> ModelSettings {
+        ModelSettings {
+            models: Models {
+                zoo: models
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), ZooEntry::from(*v)))
+                    .collect(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zoo_is_validated() {
+        assert!(with_zoo(&[("plus3", "plus3/0.1.0")]).validate().is_ok());
+        assert!(with_zoo(&[("plus3", "bindle://plus3/0.1.0")])
+            .validate()
+            .is_ok());
+        assert!(
+            with_zoo(&[("plus3", "oci://localhost:5000/models/plus3:0.1.0")])
+                .validate()
+                .is_ok()
+        );
+        assert_eq!(
+            with_zoo(&[("plus3", "oci://plus3:0.1.0")]).problems().len(),
+            1
+        );
+
+        let problems = with_zoo(&[
+            ("", "identity_model/0.2.0"),
+            ("plus3", "plus3"),
+            ("s3", "s3://bucket/model"),
+            ("twice", "identity_model/0.2.0"),
+        ])
+        .problems();
+
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert_eq!(with_zoo(&[]).problems().len(), 1);
+    }
+
+    #[test]
+    fn conflicting_options_are_reported() {
+        let mut settings = with_zoo(&[("plus3", "plus3/0.1.0")]);
+        settings.lazy_load = Some(true);
+        settings.warmup.iterations = 1;
+        settings.recording.sample_rate = 2.0;
+
+        assert_eq!(settings.problems().len(), 2);
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn settings_are_read_from_json() {
+        let values = values(&[(
+            "config_json",
+            r#"{"models":{"zoo":{"plus3":"plus3/0.1.0"}}}"#,
+        )]);
+        let settings = load_settings_with_env(&values, vec![]).unwrap();
+
+        assert_eq!(settings.models.zoo["plus3"].reference(), "plus3/0.1.0");
+    }
+
+    #[test]
+    fn settings_are_read_from_b64() {
+        let config = base64::encode(r#"{"models":{"zoo":{"plus3":"plus3/0.1.0"}}}"#);
+        let settings = load_settings_with_env(&values(&[("config_b64", &config)]), vec![]).unwrap();
+
+        assert_eq!(settings.models.zoo["plus3"].reference(), "plus3/0.1.0");
+    }
+
+    #[test]
+    fn settings_are_read_from_toml() {
+        let toml = "lazy_load = false\n[models.zoo]\nplus3 = \"plus3/0.1.0\"\n";
+        let settings = load_settings_with_env(&values(&[("config_toml", toml)]), vec![]).unwrap();
+
+        assert_eq!(settings.models.zoo["plus3"].reference(), "plus3/0.1.0");
+        assert_eq!(settings.lazy_load, Some(false));
+    }
+
+    #[test]
+    fn settings_are_read_from_file() {
+        let values = values(&[("config_file", "../../deploy/actor_config.toml")]);
+        let settings = load_settings_with_env(&values, vec![]).unwrap();
+
+        assert_eq!(settings.models.zoo["plus3"].reference(), "plus3/0.1.0");
+        assert!(load_settings_file(Path::new("../../deploy/actor_config.json")).is_ok());
+    }
+
+    #[test]
+    fn later_sources_take_precedence() {
+        let values = values(&[
+            ("config_file", "../../deploy/actor_config.toml"),
+            (
+                "config_json",
+                r#"{"models":{"zoo":{"plus3":"plus3/0.2.0"}}}"#,
+            ),
+            ("lazy_load", "true"),
+        ]);
+        let env = vec![
+            (
+                "MLINFERENCE_MODELS__ZOO__MOBILENET".to_string(),
+                "mobilenetv2-7/0.2.0".to_string(),
+            ),
+            ("MLINFERENCE_CACHE__CAPACITY".to_string(), "16".to_string()),
+            (
+                "MLINFERENCE_MEMORY__BUDGET_BYTES".to_string(),
+                "1024".to_string(),
+            ),
+            ("UNRELATED".to_string(), "1".to_string()),
+        ];
+        let settings = load_settings_with_env(&values, env).unwrap();
+
+        // merged rather than replaced
+        assert_eq!(
+            settings.models.zoo["identity"].reference(),
+            "identity_model/0.2.0"
+        );
+        assert_eq!(settings.models.zoo["plus3"].reference(), "plus3/0.2.0");
+        assert_eq!(
+            settings.models.zoo["mobilenet"].reference(),
+            "mobilenetv2-7/0.2.0"
+        );
+        assert_eq!(settings.lazy_load, Some(true));
+        assert_eq!(settings.cache.capacity, 16);
+    }
+
+    #[test]
+    fn zoo_entries_may_carry_options() {
+        let json = r#"{"models":{"zoo":{
+            "mobilenetv1tpu":{"reference":"mobilenetv1_quant_edgetpu/0.1.0","execution_target":"tpu","threads":1},
+            "mobilenetv27":"mobilenetv2-7/0.1.0"
+        }}}"#;
+        let settings = load_settings_with_env(&values(&[("config_json", json)]), vec![]).unwrap();
+
+        let tpu = settings.models.zoo["mobilenetv1tpu"].options();
+        assert_eq!(tpu.reference, "mobilenetv1_quant_edgetpu/0.1.0");
+        assert_eq!(tpu.execution_target, Some(crate::ExecutionTarget::Tpu));
+        assert_eq!(tpu.threads, Some(1));
+        assert_eq!(settings.models.zoo["mobilenetv27"].options().threads, None);
+    }
+
+    #[test]
+    fn zoo_entry_options_are_validated() {
+        let mut settings = with_zoo(&[]);
+        settings.models.zoo.insert(
+            "mobilenetv1tpu".to_string(),
+            ZooEntry::Options(ModelOptions {
+                reference: "mobilenetv1_quant_edgetpu/0.1.0".to_string(),
+                execution_target: Some(crate::ExecutionTarget::Tpu),
+                threads: Some(4),
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(settings.problems().len(), 1);
+    }
+
+    #[test]
+    fn fallback_targets_must_not_repeat() {
+        let mut settings = with_zoo(&[]);
+        settings.models.zoo.insert(
+            "mobilenetv1tpu".to_string(),
+            ZooEntry::Options(ModelOptions {
+                reference: "mobilenetv1_quant_edgetpu/0.1.0".to_string(),
+                execution_target: Some(crate::ExecutionTarget::Tpu),
+                fallback_targets: Some(vec![crate::ExecutionTarget::Tpu]),
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(settings.problems().len(), 1);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let json = r#"{"models":{"zoo":{"plus3":"plus3/0.1.0"}},"values":{"lazy_load":false}}"#;
+        let e = serde_json::from_str::<ModelSettings>(json).unwrap_err();
+
+        assert!(e.to_string().contains("unknown field `values`"));
     }
 }
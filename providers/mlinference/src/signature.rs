@@ -0,0 +1,233 @@
+//! Verification of model bindles
+//!
+//! Invoices are verified by bindle's signing model: each signature names its
+//! signer and role and is checked against a keyring of trusted public keys,
+//! the format of bindle's `keyring.toml`. An invoice must carry a creator
+//! signature by a trusted key. Unsigned invoices are refused unless
+//! `allow_unsigned` is set, wrongly signed ones are refused in any case.
+//! Parcels are checked against the sha256 declared by the invoice.
+use crate::{
+    bindle_loader::{BindleError, BindleResult},
+    sha256_hex,
+};
+use bindle::{signature::KeyRing, SignatureRole, VerificationStrategy};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Settings of the verification of model bindles
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SignatureSettings {
+    /// keyring of the trusted keys in the format of bindle's 'keyring.toml'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyring: Option<PathBuf>,
+
+    /// load models of unsigned invoices, e.g. for development
+    #[serde(default)]
+    pub allow_unsigned: bool,
+}
+
+/// Verifies invoices against a keyring of trusted keys
+#[derive(Clone)]
+pub struct InvoiceVerifier {
+    keyring: Option<KeyRing>,
+    allow_unsigned: bool,
+}
+
+impl InvoiceVerifier {
+    pub fn new(keyring: Option<KeyRing>, allow_unsigned: bool) -> Self {
+        InvoiceVerifier {
+            keyring,
+            allow_unsigned,
+        }
+    }
+
+    /// verifier of the given settings, reading the keyring if configured
+    pub fn from_settings(settings: &SignatureSettings) -> BindleResult<Self> {
+        let keyring = match &settings.keyring {
+            Some(path) => {
+                let toml = std::fs::read_to_string(path).map_err(|e| {
+                    log::error!("keyring '{}' could not be read: {}", path.display(), e);
+                    BindleError::BindleKeyringError(format!("{}: {}", path.display(), e))
+                })?;
+                let keyring: KeyRing = toml::from_str(&toml).map_err(|e| {
+                    log::error!("keyring '{}' could not be parsed: {}", path.display(), e);
+                    BindleError::BindleKeyringError(format!("{}: {}", path.display(), e))
+                })?;
+                log::info!(
+                    "verifying invoices by {} trusted key(s) of '{}'",
+                    keyring.key.len(),
+                    path.display()
+                );
+                Some(keyring)
+            }
+            None => None,
+        };
+
+        Ok(InvoiceVerifier::new(keyring, settings.allow_unsigned))
+    }
+
+    /// Verifies the signatures of an invoice, returning its creator or None
+    /// if the invoice is unsigned and unsigned invoices are allowed.
+    pub fn verify(&self, invoice: &bindle::Invoice) -> BindleResult<Option<String>> {
+        let id = invoice.bindle.id.to_string();
+        let signed = invoice.signature.as_ref().is_some_and(|s| !s.is_empty());
+
+        match (&self.keyring, signed) {
+            (_, false) if self.allow_unsigned => {
+                log::warn!(
+                    "'{}' is not signed, loading it as unsigned models are allowed",
+                    id
+                );
+                Ok(None)
+            }
+            (_, false) => {
+                log::error!("'{}' is not signed!", id);
+                Err(BindleError::BindleUnsignedInvoiceError(id))
+            }
+            (None, true) => {
+                log::error!(
+                    "'{}' is signed, but no keyring of trusted keys is configured",
+                    id
+                );
+                Err(BindleError::BindleSignatureError(
+                    id,
+                    "no keyring of trusted keys is configured".to_string(),
+                ))
+            }
+            (Some(keyring), true) => {
+                VerificationStrategy::CreativeIntegrity
+                    .verify(invoice.clone(), keyring)
+                    .map_err(|e| {
+                        log::error!("'{}' failed signature verification: {}", id, e);
+                        BindleError::BindleSignatureError(id.clone(), e.to_string())
+                    })?;
+
+                let signer = invoice
+                    .signature
+                    .iter()
+                    .flatten()
+                    .find(|s| s.role == SignatureRole::Creator)
+                    .map(|s| s.by.clone());
+                log::info!(
+                    "'{}' is signed by '{}'",
+                    id,
+                    signer.as_deref().unwrap_or("?")
+                );
+
+                Ok(signer)
+            }
+        }
+    }
+}
+
+/// checks a downloaded parcel against the sha256 its invoice declares
+pub fn verify_parcel(parcel: &bindle::Parcel, data: &[u8]) -> BindleResult<()> {
+    let sha256 = sha256_hex(data);
+
+    if sha256 != parcel.label.sha256 {
+        log::error!(
+            "parcel '{}' has sha256 '{}', its invoice declares '{}'",
+            parcel.label.name,
+            sha256,
+            parcel.label.sha256
+        );
+        return Err(BindleError::BindleParcelHashMismatchError(
+            parcel.label.name.to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::invoice::single_model_invoice;
+    use bindle::{
+        invoice::{sign, Signed},
+        signature::{KeyEntry, SecretKeyEntry},
+    };
+
+    fn unsigned_invoice() -> bindle::Invoice {
+        single_model_invoice(b"m")
+    }
+
+    fn creator_key(label: &str) -> SecretKeyEntry {
+        SecretKeyEntry::new(label.to_string(), vec![SignatureRole::Creator])
+    }
+
+    fn signed_invoice(key: &SecretKeyEntry) -> bindle::Invoice {
+        sign(unsigned_invoice(), vec![(SignatureRole::Creator, key)])
+            .unwrap()
+            .signed()
+    }
+
+    fn trusting(key: &SecretKeyEntry) -> InvoiceVerifier {
+        let keyring = KeyRing::new(vec![KeyEntry::try_from(key.clone()).unwrap()]);
+
+        InvoiceVerifier::new(Some(keyring), false)
+    }
+
+    #[test]
+    fn unsigned_invoices_are_refused_by_default() {
+        let verifier = InvoiceVerifier::from_settings(&SignatureSettings::default()).unwrap();
+
+        assert!(matches!(
+            verifier.verify(&unsigned_invoice()),
+            Err(BindleError::BindleUnsignedInvoiceError(..))
+        ));
+    }
+
+    #[test]
+    fn unsigned_invoices_may_be_allowed() {
+        let verifier = InvoiceVerifier::new(None, true);
+
+        assert_eq!(verifier.verify(&unsigned_invoice()).unwrap(), None);
+    }
+
+    #[test]
+    fn parcels_are_checked_against_their_hash() {
+        let invoice = unsigned_invoice();
+        let parcel = &invoice.parcel.as_ref().unwrap()[0];
+
+        assert!(verify_parcel(parcel, b"m").is_ok());
+        assert!(matches!(
+            verify_parcel(parcel, b"tampered"),
+            Err(BindleError::BindleParcelHashMismatchError(..))
+        ));
+    }
+
+    #[test]
+    fn invoices_signed_by_trusted_keys_pass() {
+        let key = creator_key("Model Creator <creator@example.com>");
+
+        assert_eq!(
+            trusting(&key).verify(&signed_invoice(&key)).unwrap(),
+            Some("Model Creator <creator@example.com>".to_string())
+        );
+    }
+
+    #[test]
+    fn invoices_signed_by_untrusted_keys_are_refused() {
+        let trusted = creator_key("Trusted <trusted@example.com>");
+        let untrusted = creator_key("Untrusted <untrusted@example.com>");
+
+        assert!(matches!(
+            trusting(&trusted).verify(&signed_invoice(&untrusted)),
+            Err(BindleError::BindleSignatureError(..))
+        ));
+    }
+
+    #[test]
+    fn tampered_invoices_are_refused() {
+        let key = creator_key("Model Creator <creator@example.com>");
+        let mut invoice = signed_invoice(&key);
+        invoice.parcel.as_mut().unwrap()[0].label.sha256 = sha256_hex(b"tampered");
+
+        assert!(matches!(
+            trusting(&key).verify(&invoice),
+            Err(BindleError::BindleSignatureError(..))
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{Engine, ModelContext};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use wasmcloud_interface_mlinference::{Tensor, ValueType, TENSOR_FLAG_ROW_MAJOR};
+
+/// Content of generated warm-up tensors
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WarmupFill {
+    #[default]
+    Zeros,
+    Random,
+}
+
+/// Warm-up inferences run before a model is marked ready
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WarmupSettings {
+    /// number of warm-up inferences per model, 0 disables warm-up
+    #[serde(default)]
+    pub iterations: u32,
+
+    /// content of generated tensors, used if the bindle has no 'warmup' parcels
+    #[serde(default)]
+    pub fill: WarmupFill,
+}
+
+impl WarmupSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.iterations > 0
+    }
+}
+
+/// WarmupResult
+pub type WarmupResult<T> = Result<T, WarmupError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WarmupError {
+    #[error("metadata of model '{0}' lacks 'tensor_dimensions_in'")]
+    MissingDimensions(String),
+
+    #[error("tensor type '{0:?}' is not supported for warm-up")]
+    UnsupportedValueType(ValueType),
+
+    #[error("warm-up sample has {0} bytes, model expects {1}")]
+    SampleSizeMismatch(usize, usize),
+
+    #[error("warm-up inference failed: {0}")]
+    Inference(#[from] crate::inference::InferenceError),
+}
+
+/// size in bytes of a single element
+fn element_size(value_type: &ValueType) -> WarmupResult<usize> {
+    match value_type {
+        ValueType::ValueF32 => Ok(4),
+        ValueType::ValueU8 => Ok(1),
+        other => Err(WarmupError::UnsupportedValueType(other.clone())),
+    }
+}
+
+/// Builds the tensors to feed during warm-up.
+/// Samples shipped with the bindle take precedence over generated tensors.
+pub fn warmup_tensors(
+    context: &ModelContext,
+    samples: Vec<Vec<u8>>,
+    fill: &WarmupFill,
+) -> WarmupResult<Vec<Tensor>> {
+    let dimensions = context
+        .tensor_dimensions_in
+        .clone()
+        .ok_or_else(|| WarmupError::MissingDimensions(context.bindle_url.clone()))?;
+
+    let size = dimensions.iter().product::<u32>() as usize * element_size(&context.value_type)?;
+
+    let data: Vec<Vec<u8>> = if samples.is_empty() {
+        vec![match fill {
+            WarmupFill::Zeros => vec![0u8; size],
+            WarmupFill::Random => random_data(&context.value_type, size),
+        }]
+    } else {
+        samples
+    };
+
+    data.into_iter()
+        .map(|d| {
+            if d.len() != size {
+                return Err(WarmupError::SampleSizeMismatch(d.len(), size));
+            }
+            Ok(Tensor {
+                value_types: vec![context.value_type.clone()],
+                dimensions: dimensions.clone(),
+                flags: TENSOR_FLAG_ROW_MAJOR,
+                data: d,
+            })
+        })
+        .collect()
+}
+
+fn random_data(value_type: &ValueType, size: usize) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    match value_type {
+        ValueType::ValueF32 => (0..size / 4)
+            .flat_map(|_| rng.gen::<f32>().to_le_bytes())
+            .collect(),
+        _ => (0..size).map(|_| rng.gen::<u8>()).collect(),
+    }
+}
+
+/// Runs `iterations` inferences, cycling through the given tensors.
+/// Any failure is reported such that the model is not marked ready.
+pub async fn run_warmup(
+    engine: &Engine,
+    context: &ModelContext,
+    tensors: &[Tensor],
+    iterations: u32,
+) -> WarmupResult<()> {
+    for (i, tensor) in tensors.iter().cycle().take(iterations as usize).enumerate() {
+        let start = Instant::now();
+
+        engine
+            .set_input(context.graph_execution_context, 0, tensor)
+            .await?;
+        engine.compute(context.graph_execution_context).await?;
+        engine
+            .get_output(context.graph_execution_context, 0)
+            .await?;
+
+        log::debug!(
+            "run_warmup() - inference {} of '{}' took {:?}",
+            i,
+            context.bindle_url,
+            start.elapsed()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context() -> ModelContext {
+        ModelContext {
+            tensor_dimensions_in: Some(vec![1, 2, 3]),
+            ..ModelContext::default()
+        }
+    }
+
+    #[test]
+    fn zero_tensor_matches_metadata() {
+        let tensors = warmup_tensors(&context(), vec![], &WarmupFill::Zeros).unwrap();
+
+        assert_eq!(tensors.len(), 1);
+        assert_eq!(tensors[0].dimensions, vec![1, 2, 3]);
+        assert_eq!(tensors[0].data, vec![0u8; 24]);
+    }
+
+    #[test]
+    fn samples_take_precedence() {
+        let tensors = warmup_tensors(
+            &context(),
+            vec![vec![1u8; 24], vec![2u8; 24]],
+            &WarmupFill::Random,
+        )
+        .unwrap();
+        assert_eq!(tensors.len(), 2);
+        assert_eq!(tensors[1].data, vec![2u8; 24]);
+
+        assert!(matches!(
+            warmup_tensors(&context(), vec![vec![0u8; 3]], &WarmupFill::Zeros),
+            Err(WarmupError::SampleSizeMismatch(3, 24))
+        ));
+    }
+}
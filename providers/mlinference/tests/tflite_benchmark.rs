@@ -0,0 +1,84 @@
+//! benchmarks of the TfLite interpreter options
+//!
+//! cargo test --release --features tflite --test tflite_benchmark -- --ignored --nocapture
+//!
+//! With '--features xnnpack' the XNNPACK delegate applies to every run.
+#![cfg(feature = "tflite")]
+
+use std::time::{Duration, Instant};
+use wasmcloud_interface_mlinference::{Tensor, ValueType, TENSOR_FLAG_ROW_MAJOR};
+use wasmcloud_provider_mlinference::{
+    introspect, ExecutionTarget, GraphEncoding, InferenceEngine, ModelMetadata, TfLiteEngine,
+    TfLiteOptions,
+};
+
+const ITERATIONS: u32 = 50;
+const THREADS: [i32; 3] = [1, 2, 4];
+
+/// mean duration of a single inference of 'model' with the given options
+async fn benchmark(model: &[u8], options: TfLiteOptions) -> Duration {
+    let engine = TfLiteEngine::default();
+    let metadata = ModelMetadata {
+        graph_encoding: GraphEncoding::TfLite,
+        tflite: Some(options),
+        ..Default::default()
+    };
+
+    let dims = introspect(model, &GraphEncoding::TfLite)
+        .tensor_dimensions_in
+        .expect("model declares its input dimensions");
+    let input = Tensor {
+        value_types: vec![ValueType::ValueU8],
+        dimensions: dims.clone(),
+        flags: TENSOR_FLAG_ROW_MAJOR,
+        data: vec![128; dims.iter().product::<u32>() as usize],
+    };
+
+    let graph = engine.load(model).await.unwrap();
+    engine.configure(graph, &metadata).await.unwrap();
+    let gec = engine
+        .init_execution_context(graph, &ExecutionTarget::Cpu, &GraphEncoding::TfLite)
+        .await
+        .unwrap();
+
+    // the first inference allocates, it is not measured
+    engine.set_input(gec, 0, &input).await.unwrap();
+    engine.compute(gec).await.unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        engine.set_input(gec, 0, &input).await.unwrap();
+        engine.compute(gec).await.unwrap();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+async fn benchmark_model(path: &str) {
+    let model = std::fs::read(path).unwrap();
+
+    for threads in THREADS {
+        let options = TfLiteOptions {
+            threads: Some(threads),
+            ..Default::default()
+        };
+        println!(
+            "{}: threads {}, xnnpack {}: {:?} per inference",
+            path,
+            threads,
+            cfg!(feature = "xnnpack"),
+            benchmark(&model, options).await
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn mobilenetv1_uint8_quant() {
+    benchmark_model("../../bindle/models/mobilenetv1_uint8_quant.tflite").await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn mobilenetv2_uint8_quant() {
+    benchmark_model("../../bindle/models/mobilenetv2_uint8_quant.tflite").await;
+}